@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+/// Aborts requests and websocket connections from anywhere, e.g. another task or thread.
+///
+/// Insert a handle into the extensions of a request to attach it:
+/// `request.extensions_mut().insert(handle.clone())`. Once [`Self::abort`] is called, the pending
+/// [`RequestSend`](crate::RequestSend), reads from its [`ResponseBody`](crate::ResponseBody) and a websocket
+/// connection upgraded from the request fail with [`HttpError::Aborted`](crate::HttpError::Aborted), closing the connection. Aborting is
+/// permanent and applies to all requests the handle is attached to. Clones abort the same requests.
+#[derive(Clone, Default)]
+pub struct AbortHandle {
+    state: Arc<AbortState>,
+}
+
+#[derive(Default)]
+struct AbortState {
+    aborted: AtomicBool,
+    next_id: AtomicU64,
+    wakers: Mutex<HashMap<u64, Waker>>,
+}
+
+impl AbortHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn abort(&self) {
+        self.state.aborted.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.state.wakers.lock().unwrap());
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+    pub fn is_aborted(&self) -> bool {
+        self.state.aborted.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn listen(&self) -> AbortListener {
+        AbortListener {
+            handle: self.clone(),
+            id: self.state.next_id.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Wakes the task of an operation when its handle is aborted.
+pub(crate) struct AbortListener {
+    handle: AbortHandle,
+    id: u64,
+}
+
+impl AbortListener {
+    pub fn poll_aborted(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.handle.is_aborted() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.handle.state.wakers.lock().unwrap();
+        // checked again under the lock, as abort takes the wakers after setting the flag
+        if self.handle.is_aborted() {
+            return Poll::Ready(());
+        }
+        match wakers.get_mut(&self.id) {
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => drop(wakers.insert(self.id, cx.waker().clone())),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for AbortListener {
+    fn drop(&mut self) {
+        self.handle.state.wakers.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Wake},
+        time::Duration,
+    };
+
+    use futures::executor::block_on;
+    use http::Request;
+
+    use super::AbortHandle;
+    use crate::test_util::{read_head, serve};
+    use crate::{HttpError, RequestWithoutBodyExt};
+
+    /// Serves a single request, writing `response` and keeping the connection open until the client closes it.
+    fn serve_stalled(response: &'static [u8]) -> std::net::SocketAddr {
+        serve(move |mut stream| {
+            read_head(&mut stream);
+            stream.write_all(response).unwrap();
+            let _ = stream.read(&mut [0; 1]);
+        })
+    }
+
+    fn abort_later(handle: &AbortHandle) -> std::thread::JoinHandle<()> {
+        let handle = handle.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            handle.abort();
+        })
+    }
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn abort_wakes_listeners() {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = counter.clone().into();
+        let mut cx = Context::from_waker(&waker);
+        let handle = AbortHandle::new();
+        let (first, second) = (handle.listen(), handle.listen());
+        assert!(first.poll_aborted(&mut cx).is_pending());
+        assert!(second.poll_aborted(&mut cx).is_pending());
+        drop(second);
+        handle.clone().abort();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(first.poll_aborted(&mut cx).is_ready());
+        assert!(handle.listen().poll_aborted(&mut cx).is_ready());
+        assert!(handle.state.wakers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn abort_pending_request() {
+        let addr = serve_stalled(b"");
+        let handle = AbortHandle::new();
+        let mut request = Request::get(format!("http://{}/", addr)).body(()).unwrap();
+        request.extensions_mut().insert(handle.clone());
+        let aborter = abort_later(&handle);
+        let result = block_on(request.send(()));
+        assert!(matches!(result, Err(HttpError::Aborted)), "{:?}", result.map(|_| ()));
+        aborter.join().unwrap();
+    }
+
+    #[test]
+    fn abort_body_read() {
+        let addr = serve_stalled(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\npartial");
+        let handle = AbortHandle::new();
+        let mut request = Request::get(format!("http://{}/", addr)).body(()).unwrap();
+        request.extensions_mut().insert(handle.clone());
+        let mut response = block_on(request.send(())).unwrap();
+        let aborter = abort_later(&handle);
+        let err = block_on(response.body_mut().bytes(None)).unwrap_err();
+        assert!(
+            matches!(err.get_ref().and_then(|err| err.downcast_ref()), Some(HttpError::Aborted)),
+            "{:?}",
+            err
+        );
+        aborter.join().unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use http::StatusCode;
+
+use crate::{HttpError, TransportError};
+
+type StateCallback = Arc<dyn Fn(&str, CircuitState) + Send + Sync>;
+
+/// Stops sending requests to origins that keep failing, see [`Connector::with_circuit_breaker`](crate::Connector::with_circuit_breaker).
+///
+/// Failures are connection errors, timeouts, I/O errors and the statuses 502, 503 and 504. By default the
+/// circuit of an origin opens after 5 consecutive failures and requests fail with [`HttpError::CircuitOpen`]
+/// without connecting. After 30s the circuit becomes half-open and a probe request is let through, closing
+/// the circuit on success and opening it again on failure. Clones share the state of all origins.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    consecutive_failures: u32,
+    failure_rate: Option<(f64, usize)>,
+    open_duration: Duration,
+    probes: u32,
+    on_state_change: Option<StateCallback>,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+struct Circuit {
+    state: CircuitState,
+    consecutive_failures: u32,
+    outcomes: VecDeque<bool>,
+    opened_at: Instant,
+    probes_in_flight: u32,
+    probe_successes: u32,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            failure_rate: None,
+            open_duration: Duration::from_secs(30),
+            probes: 1,
+            on_state_change: None,
+            circuits: Arc::default(),
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_consecutive_failures(mut self, failures: u32) -> Self {
+        self.consecutive_failures = failures.max(1);
+        self
+    }
+    /// Also opens the circuit if at least `rate` (e.g. `0.5`) of the last `window` requests failed.
+    pub fn with_failure_rate(mut self, rate: f64, window: usize) -> Self {
+        self.failure_rate = Some((rate, window.max(1)));
+        self
+    }
+    /// How long the circuit stays open before probe requests are let through.
+    pub fn with_open_duration(mut self, duration: Duration) -> Self {
+        self.open_duration = duration;
+        self
+    }
+    /// Number of successful probe requests needed to close a half-open circuit, which are sent concurrently.
+    pub fn with_half_open_probes(mut self, probes: u32) -> Self {
+        self.probes = probes.max(1);
+        self
+    }
+    /// Calls `callback` with the origin, e.g. `https://example.com:443`, and the new state on every state change.
+    pub fn with_state_change_callback(mut self, callback: impl Fn(&str, CircuitState) + Send + Sync + 'static) -> Self {
+        self.on_state_change = Some(Arc::new(callback));
+        self
+    }
+
+    /// Returns the current state of the circuit of `origin`.
+    pub fn state(&self, origin: &str) -> CircuitState {
+        let circuits = self.circuits.lock().unwrap();
+        circuits.get(origin).map_or(CircuitState::Closed, |circuit| circuit.state)
+    }
+
+    pub(crate) fn acquire(&self, origin: String) -> Result<CircuitPermit, HttpError> {
+        let mut circuits = self.circuits.lock().unwrap();
+        let probe = match circuits.get_mut(&origin) {
+            // origins without a circuit are closed
+            None => false,
+            Some(circuit) => match circuit.state {
+                CircuitState::Closed => false,
+                CircuitState::Open if circuit.opened_at.elapsed() >= self.open_duration => {
+                    circuit.state = CircuitState::HalfOpen;
+                    circuit.probes_in_flight = 1;
+                    drop(circuits);
+                    self.notify(&origin, CircuitState::HalfOpen);
+                    true
+                }
+                CircuitState::HalfOpen if circuit.probes_in_flight + circuit.probe_successes < self.probes => {
+                    circuit.probes_in_flight += 1;
+                    true
+                }
+                CircuitState::Open | CircuitState::HalfOpen => return Err(HttpError::CircuitOpen(origin)),
+            },
+        };
+        Ok(CircuitPermit {
+            breaker: self.clone(),
+            origin,
+            probe,
+            recorded: false,
+        })
+    }
+
+    fn record(&self, origin: &str, probe: bool, failure: bool) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = match circuits.get_mut(origin) {
+            Some(circuit) => circuit,
+            None if probe || !failure => return,
+            None => circuits.entry(origin.to_string()).or_insert_with(Circuit::new),
+        };
+        let state = match (circuit.state, probe) {
+            (CircuitState::Closed, false) => {
+                circuit.consecutive_failures = if failure { circuit.consecutive_failures + 1 } else { 0 };
+                circuit.outcomes.push_back(failure);
+                let rate_exceeded = match self.failure_rate {
+                    Some((rate, window)) => {
+                        if circuit.outcomes.len() > window {
+                            circuit.outcomes.pop_front();
+                        }
+                        let failures = circuit.outcomes.iter().filter(|failure| **failure).count();
+                        circuit.outcomes.len() == window && failures as f64 >= rate * window as f64
+                    }
+                    None => {
+                        circuit.outcomes.clear();
+                        false
+                    }
+                };
+                if circuit.consecutive_failures >= self.consecutive_failures || rate_exceeded {
+                    circuit.open()
+                } else {
+                    // circuits without recent failures are recreated on the next failure
+                    if circuit.consecutive_failures == 0 && !circuit.outcomes.contains(&true) {
+                        circuits.remove(origin);
+                    }
+                    return;
+                }
+            }
+            (CircuitState::HalfOpen, true) => {
+                circuit.probes_in_flight = circuit.probes_in_flight.saturating_sub(1);
+                if failure {
+                    circuit.open()
+                } else {
+                    circuit.probe_successes += 1;
+                    if circuit.probe_successes < self.probes {
+                        return;
+                    }
+                    circuits.remove(origin);
+                    CircuitState::Closed
+                }
+            }
+            // outcomes of requests started before the last state change
+            _ => return,
+        };
+        drop(circuits);
+        self.notify(origin, state);
+    }
+
+    fn release_probe(&self, origin: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(origin).filter(|circuit| circuit.state == CircuitState::HalfOpen) {
+            circuit.probes_in_flight = circuit.probes_in_flight.saturating_sub(1);
+        }
+    }
+
+    fn notify(&self, origin: &str, state: CircuitState) {
+        log::debug!("circuit of {} is {:?}", origin, state);
+        if let Some(callback) = &self.on_state_change {
+            callback(origin, state);
+        }
+    }
+}
+
+impl Circuit {
+    fn new() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            outcomes: VecDeque::new(),
+            opened_at: Instant::now(),
+            probes_in_flight: 0,
+            probe_successes: 0,
+        }
+    }
+    fn open(&mut self) -> CircuitState {
+        *self = Self {
+            state: CircuitState::Open,
+            ..Self::new()
+        };
+        CircuitState::Open
+    }
+}
+
+/// Permission to send a request, recording its outcome.
+pub(crate) struct CircuitPermit {
+    breaker: CircuitBreaker,
+    origin: String,
+    probe: bool,
+    recorded: bool,
+}
+
+impl CircuitPermit {
+    pub fn record<B>(mut self, result: &Result<http::Response<B>, HttpError>) {
+        self.recorded = true;
+        self.breaker.record(&self.origin, self.probe, is_upstream_failure(result));
+    }
+}
+
+/// Whether a request failed because of its upstream: connection errors, timeouts, I/O errors and the
+/// statuses 502, 503 and 504.
+pub(crate) fn is_upstream_failure<B>(result: &Result<http::Response<B>, HttpError>) -> bool {
+    match result {
+        Ok(response) => matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(HttpError::ConnectError(err)) => matches!(
+            err,
+            TransportError::TcpConnect(_) | TransportError::TlsConnect(_) | TransportError::Timeout(_)
+        ),
+        Err(HttpError::Timeout(_) | HttpError::IoError(_)) => true,
+        Err(_) => false,
+    }
+}
+
+impl Drop for CircuitPermit {
+    fn drop(&mut self) {
+        if self.probe && !self.recorded {
+            self.breaker.release_probe(&self.origin);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use http::Response;
+
+    use super::{CircuitBreaker, CircuitState};
+    use crate::{HttpError, TimeoutPhase};
+
+    #[test]
+    fn open_and_recover() {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = changes.clone();
+        let breaker = CircuitBreaker::new()
+            .with_consecutive_failures(2)
+            .with_open_duration(Duration::from_millis(20))
+            .with_state_change_callback(move |_, state| recorded.lock().unwrap().push(state));
+        let origin = "http://example.com:80".to_string();
+        let failure: Result<Response<()>, _> = Err(HttpError::Timeout(TimeoutPhase::Connect));
+        let success = Ok(Response::new(()));
+        breaker.acquire(origin.clone()).unwrap().record(&failure);
+        breaker.acquire(origin.clone()).unwrap().record(&failure);
+        assert!(matches!(breaker.acquire(origin.clone()), Err(HttpError::CircuitOpen(_))));
+        std::thread::sleep(Duration::from_millis(30));
+        let probe = breaker.acquire(origin.clone()).unwrap();
+        assert!(breaker.acquire(origin.clone()).is_err());
+        drop(probe);
+        breaker.acquire(origin.clone()).unwrap().record(&success);
+        assert_eq!(breaker.state(&origin), CircuitState::Closed);
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [CircuitState::Open, CircuitState::HalfOpen, CircuitState::Closed]
+        );
+        assert!(breaker.circuits.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn closed_circuits_evicted() {
+        let breaker = CircuitBreaker::new().with_consecutive_failures(2);
+        let failure: Result<Response<()>, _> = Err(HttpError::Timeout(TimeoutPhase::Connect));
+        let success = Ok(Response::new(()));
+        for port in 0..100 {
+            breaker.acquire(format!("http://example.com:{}", port)).unwrap().record(&success);
+        }
+        assert!(breaker.circuits.lock().unwrap().is_empty());
+
+        let origin = "http://example.com:80".to_string();
+        let (first, second) = (breaker.acquire(origin.clone()).unwrap(), breaker.acquire(origin.clone()).unwrap());
+        breaker.acquire(origin.clone()).unwrap().record(&failure);
+        first.record(&success);
+        assert!(breaker.circuits.lock().unwrap().is_empty());
+        breaker.acquire(origin.clone()).unwrap().record(&failure);
+        second.record(&failure);
+        assert_eq!(breaker.state(&origin), CircuitState::Open);
+
+        let breaker = CircuitBreaker::new().with_failure_rate(0.5, 4);
+        breaker.acquire(origin.clone()).unwrap().record(&failure);
+        breaker.acquire(origin.clone()).unwrap().record(&success);
+        assert_eq!(breaker.circuits.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::HttpError;
+
+/// Limits the number of requests in flight per origin and in total, see
+/// [`Connector::with_concurrency_limiter`](crate::Connector::with_concurrency_limiter).
+///
+/// Requests over the limit wait in FIFO order before connecting. If a queue is full, requests fail with
+/// [`HttpError::QueueFull`] instead. A request stays in flight until its response body has been read to the
+/// end or dropped, or until it has been upgraded to a websocket connection. The time spent queueing is
+/// available as [`QueueTime`] in the response extensions. Clones share their limits.
+#[derive(Clone, Default)]
+pub struct ConcurrencyLimiter {
+    max_per_origin: Option<usize>,
+    max_queue: Option<usize>,
+    total: Option<Arc<Semaphore>>,
+    origins: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+/// Time a request waited for the concurrency limits, available in the response extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueTime(pub Duration);
+
+impl ConcurrencyLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_max_per_origin(mut self, max: usize) -> Self {
+        self.max_per_origin = Some(max);
+        self
+    }
+    pub fn with_max_total(mut self, max: usize) -> Self {
+        self.total = Some(Arc::new(Semaphore::new(max)));
+        self
+    }
+    /// Maximum number of requests waiting for the limit of an origin, or for the total limit.
+    pub fn with_max_queue(mut self, max: usize) -> Self {
+        self.max_queue = Some(max);
+        self
+    }
+
+    pub(crate) fn acquire(&self, origin: String) -> Queue {
+        let mut semaphores = Vec::new();
+        if let Some(max) = self.max_per_origin {
+            let mut origins = self.origins.lock().unwrap();
+            semaphores.push(origins.entry(origin.clone()).or_insert_with(|| Arc::new(Semaphore::new(max))).clone());
+        }
+        semaphores.extend(self.total.clone());
+        Queue {
+            origin,
+            semaphores,
+            permits: Vec::new(),
+            waiting: None,
+            max_queue: self.max_queue,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Semaphore granting permits in FIFO order.
+struct Semaphore {
+    state: Mutex<SemaphoreState>,
+}
+
+struct SemaphoreState {
+    available: usize,
+    next_id: u64,
+    queue: VecDeque<(u64, Waker)>,
+    granted: Vec<u64>,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(SemaphoreState {
+                available: permits,
+                next_id: 0,
+                queue: VecDeque::new(),
+                granted: Vec::new(),
+            }),
+        }
+    }
+    /// Takes a permit or queues for one, `waiting` holding the position in the queue.
+    fn poll_acquire(&self, waiting: &mut Option<u64>, max_queue: Option<usize>, cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+        let mut state = self.state.lock().unwrap();
+        match *waiting {
+            None if state.available > 0 && state.queue.is_empty() => {
+                state.available -= 1;
+                Poll::Ready(Ok(()))
+            }
+            None if max_queue.is_some_and(|max| state.queue.len() >= max) => Poll::Ready(Err(())),
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.queue.push_back((id, cx.waker().clone()));
+                *waiting = Some(id);
+                Poll::Pending
+            }
+            Some(id) => {
+                if let Some(index) = state.granted.iter().position(|granted| *granted == id) {
+                    state.granted.swap_remove(index);
+                    *waiting = None;
+                    return Poll::Ready(Ok(()));
+                }
+                if let Some((_, waker)) = state.queue.iter_mut().find(|(queued, _)| *queued == id) {
+                    waker.clone_from(cx.waker());
+                }
+                Poll::Pending
+            }
+        }
+    }
+    fn cancel(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(index) = state.queue.iter().position(|(queued, _)| *queued == id) {
+            state.queue.remove(index);
+        } else if let Some(index) = state.granted.iter().position(|granted| *granted == id) {
+            state.granted.swap_remove(index);
+            drop(state);
+            self.release();
+        }
+    }
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        match state.queue.pop_front() {
+            Some((id, waker)) => {
+                state.granted.push(id);
+                drop(state);
+                waker.wake();
+            }
+            None => state.available += 1,
+        }
+    }
+}
+
+/// Future waiting for the permits of a request.
+pub(crate) struct Queue {
+    origin: String,
+    semaphores: Vec<Arc<Semaphore>>,
+    permits: Vec<SemaphorePermit>,
+    waiting: Option<u64>,
+    max_queue: Option<usize>,
+    started: Instant,
+}
+
+impl Future for Queue {
+    type Output = Result<(ConcurrencyPermit, QueueTime), HttpError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        while let Some(semaphore) = this.semaphores.get(this.permits.len()) {
+            match semaphore.poll_acquire(&mut this.waiting, this.max_queue, cx) {
+                Poll::Ready(Ok(())) => this.permits.push(SemaphorePermit(semaphore.clone())),
+                Poll::Ready(Err(())) => return Poll::Ready(Err(HttpError::QueueFull(this.origin.clone()))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let permit = ConcurrencyPermit {
+            _permits: std::mem::take(&mut this.permits),
+        };
+        Poll::Ready(Ok((permit, QueueTime(this.started.elapsed()))))
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        if let (Some(id), Some(semaphore)) = (self.waiting, self.semaphores.get(self.permits.len())) {
+            semaphore.cancel(id);
+        }
+    }
+}
+
+struct SemaphorePermit(Arc<Semaphore>);
+
+impl Drop for SemaphorePermit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// Permits of a request in flight, released on drop.
+pub(crate) struct ConcurrencyPermit {
+    _permits: Vec<SemaphorePermit>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::{Context, Poll};
+
+    use futures::{executor::block_on, task::noop_waker_ref, FutureExt};
+
+    use super::ConcurrencyLimiter;
+    use crate::HttpError;
+
+    #[test]
+    fn fifo_queue() {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let limiter = ConcurrencyLimiter::new().with_max_per_origin(1).with_max_total(2).with_max_queue(2);
+        let (first, _) = block_on(limiter.acquire("a".into())).unwrap();
+        let mut second = limiter.acquire("a".into());
+        let mut third = limiter.acquire("a".into());
+        assert!(second.poll_unpin(&mut cx).is_pending());
+        assert!(third.poll_unpin(&mut cx).is_pending());
+        assert!(matches!(block_on(limiter.acquire("a".into())), Err(HttpError::QueueFull(_))));
+        let (other, _) = block_on(limiter.acquire("b".into())).unwrap();
+        assert!(limiter.acquire("c".into()).poll_unpin(&mut cx).is_pending());
+        drop(other);
+        drop(first);
+        assert!(third.poll_unpin(&mut cx).is_pending());
+        let second = match second.poll_unpin(&mut cx) {
+            Poll::Ready(result) => result.unwrap(),
+            Poll::Pending => panic!("second request still queued"),
+        };
+        drop(second);
+        assert!(third.poll_unpin(&mut cx).is_ready());
+    }
+}
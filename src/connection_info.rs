@@ -0,0 +1,62 @@
+use std::{net::SocketAddr, time::SystemTime};
+
+use futures_rustls::rustls::{client::EchStatus, CipherSuite, ProtocolVersion};
+use rustls_pki_types::CertificateDer;
+
+use crate::{tls::der, Transport};
+
+/// Details about the connection a response was received on, available in the response extensions.
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    pub peer_addr: Option<SocketAddr>,
+    pub local_addr: Option<SocketAddr>,
+    /// Set if the connection uses TLS.
+    pub tls: Option<TlsInfo>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsInfo {
+    pub protocol_version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// Certificate chain presented by the server, end-entity certificate first.
+    pub peer_certificates: Vec<CertificateDer<'static>>,
+    /// End of the validity period of the end-entity certificate.
+    pub certificate_expiry: Option<SystemTime>,
+    /// Whether Encrypted Client Hello was offered and accepted by the server.
+    pub ech_status: EchStatus,
+    /// Whether the request head was sent as TLS 1.3 early data and accepted by the server.
+    pub early_data_accepted: bool,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new(transport: &Transport, early_data: bool) -> Self {
+        let (tcp, tls) = match transport {
+            Transport::Tcp(tcp) => (tcp, None),
+            Transport::Tls(tls) => {
+                let (tcp, connection) = tls.get_ref();
+                let peer_certificates: Vec<_> = connection
+                    .peer_certificates()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|certificate| certificate.clone().into_owned())
+                    .collect();
+                let tls = TlsInfo {
+                    protocol_version: connection.protocol_version(),
+                    cipher_suite: connection.negotiated_cipher_suite().map(|suite| suite.suite()),
+                    alpn_protocol: connection.alpn_protocol().map(<[u8]>::to_vec),
+                    ech_status: connection.ech_status(),
+                    early_data_accepted: early_data && connection.is_early_data_accepted(),
+                    certificate_expiry: peer_certificates.first().and_then(|certificate| der::not_after(certificate)),
+                    peer_certificates,
+                };
+                (tcp, Some(tls))
+            }
+        };
+        Self {
+            peer_addr: tcp.peer_addr().ok(),
+            local_addr: tcp.local_addr().ok(),
+            tls,
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use std::{collections::HashMap, io, net::SocketAddr, sync::Arc, time::Duration};
+
+use async_net::TcpStream;
+use futures_rustls::rustls::ClientConfig;
+use http::{uri::Scheme, HeaderMap, Uri};
+
+use crate::http::{extract_origin, normalize_host};
+use crate::lifecycle::{Lifecycle, PreconnectKey};
+use crate::{
+    CircuitBreaker, ConcurrencyLimiter, EndpointSet, HedgePolicy, HttpError, OutboundPolicy, RateLimiter, RetryPolicy, SocketOptions, SrvResolver,
+    Timeouts, Transport, TransportError,
+};
+#[cfg(feature = "aws-lc-rs")]
+use crate::{TlsConfigBuilder, TlsConfigError};
+
+/// Settings used to open new connections for requests and websocket connections.
+///
+/// Cloning is cheap, so a connector can be shared between requests.
+#[derive(Clone)]
+pub struct Connector {
+    client_config: Arc<ClientConfig>,
+    host_client_configs: Arc<HashMap<String, Arc<ClientConfig>>>,
+    socket_options: SocketOptions,
+    policy: Option<Arc<OutboundPolicy>>,
+    timeouts: Timeouts,
+    retry_policy: Option<Arc<RetryPolicy>>,
+    hedge_policy: Option<HedgePolicy>,
+    circuit_breaker: Option<CircuitBreaker>,
+    rate_limiter: Option<RateLimiter>,
+    concurrency_limiter: Option<ConcurrencyLimiter>,
+    endpoint_sets: Arc<HashMap<String, EndpointSet>>,
+    srv_resolver: SrvResolver,
+    lifecycle: Lifecycle,
+    early_data: bool,
+    #[cfg(feature = "aws-lc-rs")]
+    ech_hosts: Arc<HashMap<String, Arc<EchHost>>>,
+}
+
+#[cfg(feature = "aws-lc-rs")]
+/// TLS config of a host using ECH, rebuilt when the server provides retry configs.
+struct EchHost {
+    builder: TlsConfigBuilder,
+    client_config: std::sync::Mutex<Arc<ClientConfig>>,
+}
+
+impl Connector {
+    pub fn new(client_config: Arc<ClientConfig>) -> Self {
+        Self {
+            client_config,
+            host_client_configs: Arc::default(),
+            socket_options: SocketOptions::default(),
+            policy: None,
+            timeouts: Timeouts::default(),
+            retry_policy: None,
+            hedge_policy: None,
+            circuit_breaker: None,
+            rate_limiter: None,
+            concurrency_limiter: None,
+            endpoint_sets: Arc::default(),
+            srv_resolver: SrvResolver::default(),
+            lifecycle: Lifecycle::default(),
+            early_data: false,
+            #[cfg(feature = "aws-lc-rs")]
+            ech_hosts: Arc::default(),
+        }
+    }
+    /// Uses a different TLS config for connections to `host`, e.g. to present a client certificate
+    /// specific to that server.
+    pub fn with_host_client_config(mut self, host: impl Into<String>, client_config: Arc<ClientConfig>) -> Self {
+        let host = normalize_host(&host.into());
+        Arc::make_mut(&mut self.host_client_configs).insert(host, client_config);
+        self
+    }
+    /// Uses the TLS config built by `builder` for connections to `host`, like [`Self::with_host_client_config`].
+    /// If the server rejects Encrypted Client Hello and provides retry configs, the connection is retried once
+    /// with a config rebuilt using them, which is also used for later connections. Requires the `aws-lc-rs` feature.
+    #[cfg(feature = "aws-lc-rs")]
+    pub fn with_host_ech(mut self, host: impl Into<String>, builder: TlsConfigBuilder) -> Result<Self, TlsConfigError> {
+        let host = normalize_host(&host.into());
+        let client_config = std::sync::Mutex::new(builder.clone().build()?);
+        Arc::make_mut(&mut self.ech_hosts).insert(host, Arc::new(EchHost { builder, client_config }));
+        Ok(self)
+    }
+    pub fn with_socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
+    /// Restricts the destinations of requests and websocket connections using this connector.
+    pub fn with_policy(mut self, policy: OutboundPolicy) -> Self {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+    /// Retries requests sent with this connector that fail with transient errors.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(Arc::new(retry_policy));
+        self
+    }
+    /// Sends a duplicate of read-only requests sent with this connector whose response head takes too long.
+    pub fn with_hedge_policy(mut self, hedge_policy: HedgePolicy) -> Self {
+        self.hedge_policy = Some(hedge_policy);
+        self
+    }
+    /// Fails requests to origins whose circuit is open without connecting.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+    /// Delays requests exceeding the limits of `rate_limiter` before connecting.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+    /// Queues requests exceeding the in-flight limits of `concurrency_limiter` before connecting.
+    pub fn with_concurrency_limiter(mut self, concurrency_limiter: ConcurrencyLimiter) -> Self {
+        self.concurrency_limiter = Some(concurrency_limiter);
+        self
+    }
+    /// Sends requests to the logical `host` to one of the endpoints of `endpoint_set`.
+    pub fn with_endpoint_set(mut self, host: impl Into<String>, endpoint_set: EndpointSet) -> Self {
+        let host = normalize_host(&host.into());
+        Arc::make_mut(&mut self.endpoint_sets).insert(host, endpoint_set);
+        self
+    }
+    /// Resolves `http+srv` and `https+srv` URIs with `srv_resolver`, e.g. to query a specific nameserver.
+    pub fn with_srv_resolver(mut self, srv_resolver: SrvResolver) -> Self {
+        self.srv_resolver = srv_resolver;
+        self
+    }
+    /// Sends the request head of `GET`, `HEAD` and `OPTIONS` requests without body as TLS 1.3 early data
+    /// (0-RTT) when resuming a session. Early data can be replayed by an attacker, so it is never used for
+    /// other requests. The TLS config must have early data enabled, see
+    /// [`TlsConfigBuilder::with_early_data`](crate::TlsConfigBuilder::with_early_data). If the server
+    /// rejects the early data, the request head is sent again after the handshake.
+    #[cfg(feature = "early-data")]
+    pub fn with_early_data(mut self, enabled: bool) -> Self {
+        self.early_data = enabled;
+        self
+    }
+    pub fn client_config(&self) -> &Arc<ClientConfig> {
+        &self.client_config
+    }
+    /// Returns the TLS config used for connections to `host`.
+    pub fn client_config_for_host(&self, host: &str) -> Arc<ClientConfig> {
+        #[cfg(feature = "aws-lc-rs")]
+        if let Some(ech_host) = self.ech_hosts.get(host) {
+            return ech_host.client_config.lock().unwrap().clone();
+        }
+        self.host_client_configs.get(host).unwrap_or(&self.client_config).clone()
+    }
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.socket_options
+    }
+    pub fn policy(&self) -> Option<&OutboundPolicy> {
+        self.policy.as_deref()
+    }
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry_policy.as_deref()
+    }
+    pub fn hedge_policy(&self) -> Option<&HedgePolicy> {
+        self.hedge_policy.as_ref()
+    }
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.circuit_breaker.as_ref()
+    }
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+    pub fn concurrency_limiter(&self) -> Option<&ConcurrencyLimiter> {
+        self.concurrency_limiter.as_ref()
+    }
+    /// Returns the endpoint set serving the logical `host`.
+    pub fn endpoint_set(&self, host: &str) -> Option<&EndpointSet> {
+        self.endpoint_sets.get(host)
+    }
+    pub fn srv_resolver(&self) -> &SrvResolver {
+        &self.srv_resolver
+    }
+    pub fn early_data(&self) -> bool {
+        self.early_data
+    }
+
+    /// Opens a connection to the origin of `uri`, including name resolution and the TLS handshake, which is
+    /// used by the next request to that origin sent with this connector or its clones having the same TLS
+    /// config, policy and socket options. Every preconnected connection serves a single request, connections
+    /// closed by the server in the meantime are discarded.
+    pub async fn preconnect(&self, uri: &Uri) -> Result<(), HttpError> {
+        let (scheme, host, port) = extract_origin(uri, &HeaderMap::new())?;
+        let https = match scheme {
+            _ if scheme == Some(Scheme::HTTP) => false,
+            _ if scheme == Some(Scheme::HTTPS) => true,
+            None => true,
+            Some(scheme) => return Err(HttpError::UnexpectedScheme(scheme)),
+        };
+        let port = port.unwrap_or(if https { 443 } else { 80 });
+        if let Some(policy) = self.policy() {
+            let scheme = if https { Scheme::HTTPS } else { Scheme::HTTP };
+            policy.check_origin(&scheme, &host, port).map_err(HttpError::PolicyViolation)?;
+        }
+        let transport = Transport::connect(self.clone(), https, &host, port, false)
+            .await
+            .map_err(|err| match err {
+                TransportError::PolicyViolation(violation) => HttpError::PolicyViolation(violation),
+                TransportError::Timeout(phase) => HttpError::Timeout(phase),
+                err => HttpError::ConnectError(err),
+            })?;
+        let origin = format!("{}://{}:{}", if https { "https" } else { "http" }, host, port);
+        self.lifecycle.put_preconnected(self.preconnect_key(origin, &host), transport)
+    }
+
+    /// Shuts down this connector and its clones: new requests and websocket connections fail with
+    /// [`HttpError::ShuttingDown`], while requests in flight (until their response body has been read or
+    /// dropped) and websocket connections (until dropped) get up to `timeout` to finish. Those still in flight
+    /// after the timeout are aborted like with an [`AbortHandle`](crate::AbortHandle). Preconnected connections
+    /// are closed, sending TLS `close_notify` within the same timeout. Returns whether everything finished in
+    /// time.
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        self.lifecycle.shutdown(timeout).await
+    }
+
+    pub(crate) fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    pub(crate) fn preconnect_key(&self, origin: String, host: &str) -> PreconnectKey {
+        let client_config = self.client_config_for_host(host);
+        PreconnectKey::new(origin, client_config, self.policy.clone(), self.socket_options.clone())
+    }
+
+    /// Rebuilds the TLS config of an ECH host with the retry configs provided by the server.
+    #[cfg(feature = "aws-lc-rs")]
+    pub(crate) fn retry_ech(
+        &self,
+        host: &str,
+        retry_configs: rustls_pki_types::EchConfigListBytes<'static>,
+    ) -> Option<Result<Arc<ClientConfig>, TlsConfigError>> {
+        let ech_host = self.ech_hosts.get(host)?;
+        let client_config = match ech_host.builder.clone().with_ech_config_list(retry_configs).build() {
+            Ok(client_config) => client_config,
+            Err(err) => return Some(Err(err)),
+        };
+        *ech_host.client_config.lock().unwrap() = client_config.clone();
+        Some(Ok(client_config))
+    }
+
+    pub(crate) async fn connect_tcp(&self, host: &str, port: u16) -> Result<TcpStream, TransportError> {
+        let addrs = async_net::resolve((host, port))
+            .await
+            .map_err(|err| TransportError::TcpConnect(Arc::new(err)))?;
+        self.connect_addrs(addrs).await
+    }
+
+    async fn connect_addrs(&self, addrs: Vec<SocketAddr>) -> Result<TcpStream, TransportError> {
+        let mut last_err = None;
+        let mut violation = None;
+        for addr in addrs {
+            if let Some(policy) = &self.policy {
+                if let Err(err) = policy.check_address(addr.ip()) {
+                    violation.get_or_insert(TransportError::PolicyViolation(err));
+                    continue;
+                }
+            }
+            match self.socket_options.connect_addr(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(TransportError::TcpConnect(Arc::new(err))),
+            }
+        }
+        // a refused address is reported rather than connect errors of other addresses
+        Err(violation.or(last_err).unwrap_or_else(|| {
+            let err = io::Error::new(io::ErrorKind::InvalidInput, "could not resolve to any address");
+            TransportError::TcpConnect(Arc::new(err))
+        }))
+    }
+}
+
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+impl Default for Connector {
+    fn default() -> Self {
+        Self::new(crate::DEFAULT_CLIENT_CONFIG.clone())
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+
+    use super::Connector;
+    use crate::{OutboundPolicy, PolicyViolation, TransportError};
+
+    #[test]
+    fn idna_host_client_config() {
+        let client_config = crate::TlsConfigBuilder::new().build().unwrap();
+        let connector = Connector::default().with_host_client_config("Bücher.example", client_config.clone());
+        assert!(Arc::ptr_eq(&connector.client_config_for_host("xn--bcher-kva.example"), &client_config));
+    }
+
+    #[test]
+    fn policy_violation_reported_over_connect_errors() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let denied = SocketAddr::from(([127, 0, 0, 1], port));
+        let refused = SocketAddr::from(([127, 0, 0, 2], port));
+        let policy = OutboundPolicy::new().with_denied_networks(["127.0.0.1/32".parse().unwrap()]);
+        let connector = Connector::default().with_policy(policy);
+        let err = block_on(connector.connect_addrs(vec![denied, refused])).unwrap_err();
+        assert!(
+            matches!(err, TransportError::PolicyViolation(PolicyViolation::Address(addr)) if addr == denied.ip()),
+            "{:?}",
+            err
+        );
+    }
+
+    #[cfg(feature = "early-data")]
+    #[test]
+    fn early_data() {
+        use std::{
+            io::{Read, Write},
+            sync::{Arc, Mutex},
+        };
+
+        use futures::AsyncReadExt;
+        use futures_rustls::rustls::ServerConfig;
+        use http::{Method, Request};
+
+        use crate::test_util::{serve_tls, server_config, Ca};
+        use crate::{ConnectionInfo, RequestWithoutBodyExt, TlsConfigBuilder};
+
+        /// Serves `localhost` accepting early data, recording the request heads received as early data.
+        fn serve(ca: &Ca) -> (u16, Arc<Mutex<Vec<String>>>) {
+            let (leaf, key) = ca.leaf("localhost");
+            let mut config = ServerConfig::clone(&server_config(vec![leaf], key));
+            config.max_early_data_size = 16384;
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let early_heads = received.clone();
+            let addr = serve_tls(Arc::new(config), move |mut stream| {
+                let (mut head, mut early) = (Vec::new(), Vec::new());
+                while !head.ends_with(b"\r\n\r\n") {
+                    if stream.conn.complete_io(&mut stream.sock).is_err() {
+                        return;
+                    }
+                    if let Some(mut early_data) = stream.conn.early_data() {
+                        let start = early.len();
+                        early_data.read_to_end(&mut early).unwrap();
+                        head.extend_from_slice(&early[start..]);
+                    }
+                    let mut buf = [0u8; 1024];
+                    match stream.conn.reader().read(&mut buf) {
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                        Err(_) => return,
+                    }
+                }
+                early_heads.lock().unwrap().push(String::from_utf8(early).unwrap());
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok")
+                    .unwrap();
+                stream.flush().unwrap();
+            });
+            (addr.port(), received)
+        }
+
+        /// Sends a request, returning whether its head was accepted as early data.
+        fn send(connector: &Connector, port: u16, method: Method) -> bool {
+            let request = Request::builder()
+                .method(method)
+                .uri(format!("https://localhost:{}/", port))
+                .body(())
+                .unwrap();
+            block_on(async {
+                let mut response = request.send_with_connector((), connector.clone()).await.unwrap();
+                let mut text = String::new();
+                response.body_mut().read_to_string(&mut text).await.unwrap();
+                assert_eq!(text, "ok");
+                response
+                    .extensions()
+                    .get::<ConnectionInfo>()
+                    .unwrap()
+                    .tls
+                    .as_ref()
+                    .unwrap()
+                    .early_data_accepted
+            })
+        }
+
+        let ca = Ca::new("root");
+        let client_config = TlsConfigBuilder::new()
+            .with_root_certificate(ca.der())
+            .with_early_data(true)
+            .build()
+            .unwrap();
+        let connector = Connector::new(client_config).with_early_data(true);
+        let (port, received) = serve(&ca);
+        assert!(!send(&connector, port, Method::GET));
+        assert!(send(&connector, port, Method::GET));
+        // never for requests that are unsafe to replay
+        assert!(!send(&connector, port, Method::DELETE));
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 3);
+            assert!(received[1].starts_with("GET / HTTP/1.1\r\n"), "{:?}", received);
+            assert!(received[0].is_empty() && received[2].is_empty(), "{:?}", received);
+        }
+        // a server not knowing the session rejects the early data, so the head is sent again after the handshake
+        let (other_port, other_received) = serve(&ca);
+        assert!(!send(&connector, other_port, Method::GET));
+        assert_eq!(*other_received.lock().unwrap(), [String::new()]);
+    }
+}
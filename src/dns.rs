@@ -0,0 +1,176 @@
+//! Minimal DNS message encoding and parsing for SRV lookups.
+
+use std::io;
+
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const RCODE_NAME_ERROR: u16 = 3;
+
+/// SRV record from an answer section.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+    pub ttl: u32,
+}
+
+/// Outcome of parsing a response.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Response {
+    Records(Vec<SrvRecord>),
+    /// The response did not fit into a UDP datagram, the query has to be repeated over TCP.
+    Truncated,
+}
+
+pub(crate) fn srv_query(id: u16, name: &str) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    for value in [id, FLAG_RECURSION_DESIRED, 1, 0, 0, 0] {
+        query.extend_from_slice(&value.to_be_bytes());
+    }
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid("invalid name"));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+pub(crate) fn parse_srv_response(id: u16, message: &[u8]) -> io::Result<Response> {
+    let mut reader = Reader { message, position: 0 };
+    if reader.u16()? != id {
+        return Err(invalid("mismatched response id"));
+    }
+    let flags = reader.u16()?;
+    if flags & FLAG_TRUNCATED != 0 {
+        return Ok(Response::Truncated);
+    }
+    match flags & 0x000f {
+        0 => {}
+        RCODE_NAME_ERROR => return Err(io::Error::new(io::ErrorKind::NotFound, "no such name")),
+        rcode => return Err(invalid(&format!("dns error code {}", rcode))),
+    }
+    let (questions, answers) = (reader.u16()?, reader.u16()?);
+    reader.bytes(4)?;
+    for _ in 0..questions {
+        reader.name()?;
+        reader.bytes(4)?;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        reader.name()?;
+        let (record_type, _class, ttl, length) = (reader.u16()?, reader.u16()?, reader.u32()?, reader.u16()? as usize);
+        let end = reader.position + length;
+        if record_type == TYPE_SRV {
+            records.push(SrvRecord {
+                priority: reader.u16()?,
+                weight: reader.u16()?,
+                port: reader.u16()?,
+                target: reader.name()?,
+                ttl,
+            });
+        }
+        reader.position = end;
+    }
+    Ok(Response::Records(records))
+}
+
+struct Reader<'a> {
+    message: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> io::Result<&[u8]> {
+        let bytes = self
+            .message
+            .get(self.position..self.position + n)
+            .ok_or_else(|| invalid("truncated message"))?;
+        self.position += n;
+        Ok(bytes)
+    }
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    /// Reads a possibly compressed name, returning it in lowercase without the trailing dot.
+    fn name(&mut self) -> io::Result<String> {
+        let mut labels = Vec::new();
+        let mut position = self.position;
+        let mut jumps = 0;
+        loop {
+            let length = *self.message.get(position).ok_or_else(|| invalid("truncated name"))? as usize;
+            match length {
+                0 => {
+                    if jumps == 0 {
+                        self.position = position + 1;
+                    }
+                    break;
+                }
+                0xc0.. => {
+                    let low = *self.message.get(position + 1).ok_or_else(|| invalid("truncated name"))? as usize;
+                    if jumps == 0 {
+                        self.position = position + 2;
+                    }
+                    jumps += 1;
+                    if jumps > 16 {
+                        return Err(invalid("name compression loop"));
+                    }
+                    position = (length & 0x3f) << 8 | low;
+                }
+                1..=63 => {
+                    let label = self
+                        .message
+                        .get(position + 1..position + 1 + length)
+                        .ok_or_else(|| invalid("truncated name"))?;
+                    labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                    position += 1 + length;
+                }
+                _ => return Err(invalid("invalid label")),
+            }
+        }
+        Ok(labels.join("."))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_srv_response, srv_query, Response, SrvRecord};
+
+    #[test]
+    fn parse_compressed_response() {
+        let mut response = srv_query(7, "_api._tcp.Service.local").unwrap();
+        response[2..4].copy_from_slice(&0x8180u16.to_be_bytes());
+        response[6..8].copy_from_slice(&1u16.to_be_bytes());
+        // answer owned by the question name, with the target in the same domain
+        response.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1, 0, 0, 0, 60, 0, 15, 0, 10, 0, 5, 0x1f, 0x90]);
+        response.extend_from_slice(&[5, b'n', b'o', b'd', b'e', b'1', 0xc0, 22]);
+        let records = match parse_srv_response(7, &response).unwrap() {
+            Response::Records(records) => records,
+            Response::Truncated => panic!("unexpected truncation"),
+        };
+        let expected = SrvRecord {
+            priority: 10,
+            weight: 5,
+            port: 8080,
+            target: "node1.service.local".to_string(),
+            ttl: 60,
+        };
+        assert_eq!(records, [expected]);
+        assert!(parse_srv_response(8, &response).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use http::{uri::PathAndQuery, Uri};
+
+use crate::{circuit::is_upstream_failure, HttpError};
+
+/// How an [`EndpointSet`] chooses the endpoint of a request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoadBalancing {
+    #[default]
+    RoundRobin,
+    /// Chooses the endpoint with the fewest requests in flight.
+    LeastOutstanding,
+    /// Chooses the endpoint with fewer requests in flight out of two random ones.
+    PowerOfTwoChoices,
+}
+
+/// Several origins serving the same service, see [`Connector::with_endpoint_set`](crate::Connector::with_endpoint_set).
+///
+/// Every request to the logical host of the set is sent to one of its endpoints, prefixing the request path
+/// with the path of the endpoint URI. Endpoints failing 3 consecutive requests (see the failures counted by
+/// [`CircuitBreaker`](crate::CircuitBreaker)) are ejected for 30s. If all endpoints are ejected, requests are
+/// sent to all of them again. A request is in flight until its response body has been read or dropped. Clones
+/// share their state.
+#[derive(Clone)]
+pub struct EndpointSet {
+    strategy: LoadBalancing,
+    max_failures: u32,
+    ejection: Duration,
+    state: Arc<EndpointSetState>,
+}
+
+struct EndpointSetState {
+    endpoints: Vec<Endpoint>,
+    next: AtomicUsize,
+}
+
+struct Endpoint {
+    uri: Uri,
+    outstanding: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl EndpointSet {
+    /// Creates a set of endpoints from absolute URIs like `https://10.0.0.1:8443` or `http://backend-1/api`.
+    pub fn new(uris: impl IntoIterator<Item = Uri>) -> Result<Self, HttpError> {
+        let endpoints = uris
+            .into_iter()
+            .map(|uri| match (uri.scheme(), uri.authority()) {
+                (Some(_), Some(_)) => Ok(Endpoint {
+                    uri,
+                    outstanding: AtomicUsize::new(0),
+                    health: Mutex::default(),
+                }),
+                _ => Err(HttpError::MissingHost),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if endpoints.is_empty() {
+            return Err(HttpError::NoEndpoints);
+        }
+        Ok(Self {
+            strategy: LoadBalancing::default(),
+            max_failures: 3,
+            ejection: Duration::from_secs(30),
+            state: Arc::new(EndpointSetState {
+                endpoints,
+                next: AtomicUsize::new(0),
+            }),
+        })
+    }
+    pub fn with_load_balancing(mut self, strategy: LoadBalancing) -> Self {
+        self.strategy = strategy;
+        self
+    }
+    /// Ejects endpoints for `duration` after `failures` consecutive failed requests.
+    pub fn with_ejection(mut self, failures: u32, duration: Duration) -> Self {
+        self.max_failures = failures.max(1);
+        self.ejection = duration;
+        self
+    }
+
+    /// Rewrites `uri` onto an endpoint, returning a guard tracking the request.
+    pub(crate) fn select(&self, uri: &Uri) -> Result<(Uri, EndpointGuard), HttpError> {
+        let endpoints = &self.state.endpoints;
+        let now = Instant::now();
+        let mut healthy: Vec<usize> = (0..endpoints.len())
+            .filter(|index| {
+                let health = endpoints[*index].health.lock().unwrap();
+                health.ejected_until.is_none_or(|until| until <= now)
+            })
+            .collect();
+        if healthy.is_empty() {
+            healthy = (0..endpoints.len()).collect();
+        }
+        let outstanding = |index: &usize| endpoints[*index].outstanding.load(Ordering::Relaxed);
+        let next = self.state.next.fetch_add(1, Ordering::Relaxed);
+        let index = match self.strategy {
+            LoadBalancing::RoundRobin => healthy[next % healthy.len()],
+            // rotating the candidates spreads requests between endpoints with equal load
+            LoadBalancing::LeastOutstanding => *healthy
+                .iter()
+                .cycle()
+                .skip(next % healthy.len())
+                .take(healthy.len())
+                .min_by_key(|index| outstanding(index))
+                .unwrap(),
+            LoadBalancing::PowerOfTwoChoices => {
+                let first = fastrand::usize(..healthy.len());
+                let second = (first + fastrand::usize(1..healthy.len().max(2))) % healthy.len();
+                [healthy[first], healthy[second]].into_iter().min_by_key(outstanding).unwrap()
+            }
+        };
+        let endpoint = &endpoints[index];
+        let prefix = endpoint.uri.path().trim_end_matches('/');
+        let path_and_query = uri.path_and_query().map_or("/", PathAndQuery::as_str);
+        let rewritten = Uri::builder()
+            .scheme(endpoint.uri.scheme().unwrap().clone())
+            .authority(endpoint.uri.authority().unwrap().clone())
+            .path_and_query(format!("{}{}", prefix, path_and_query))
+            .build()
+            .map_err(|_| HttpError::InvalidHost(endpoint.uri.to_string()))?;
+        endpoint.outstanding.fetch_add(1, Ordering::Relaxed);
+        let guard = EndpointGuard { set: self.clone(), index };
+        Ok((rewritten, guard))
+    }
+}
+
+/// Counts a request as in flight on its endpoint until dropped.
+pub(crate) struct EndpointGuard {
+    set: EndpointSet,
+    index: usize,
+}
+
+impl EndpointGuard {
+    pub fn record<B>(&self, result: &Result<http::Response<B>, HttpError>) {
+        let endpoint = &self.set.state.endpoints[self.index];
+        let mut health = endpoint.health.lock().unwrap();
+        if !is_upstream_failure(result) {
+            health.consecutive_failures = 0;
+            return;
+        }
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.set.max_failures {
+            log::debug!("ejecting endpoint {} for {:?}", endpoint.uri, self.set.ejection);
+            health.consecutive_failures = 0;
+            health.ejected_until = Some(Instant::now() + self.set.ejection);
+        }
+    }
+}
+
+impl Drop for EndpointGuard {
+    fn drop(&mut self) {
+        self.set.state.endpoints[self.index].outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Response, Uri};
+
+    use super::{EndpointSet, LoadBalancing};
+    use crate::{HttpError, TimeoutPhase};
+
+    #[test]
+    fn select_endpoints() {
+        let uris = ["http://a:8080/api/", "https://b"].map(Uri::from_static);
+        let set = EndpointSet::new(uris).unwrap().with_load_balancing(LoadBalancing::LeastOutstanding);
+        let (a, guard_a) = set.select(&Uri::from_static("http://service/users?id=1")).unwrap();
+        assert_eq!(a, "http://a:8080/api/users?id=1");
+        let (b, guard_b) = set.select(&Uri::from_static("http://service/")).unwrap();
+        assert_eq!(b, "https://b/");
+        drop(guard_b);
+        assert_eq!(set.select(&Uri::from_static("/")).unwrap().0, "https://b/");
+        let failure: Result<Response<()>, _> = Err(HttpError::Timeout(TimeoutPhase::Connect));
+        for _ in 0..3 {
+            guard_a.record(&failure);
+        }
+        drop(guard_a);
+        let set = set.with_load_balancing(LoadBalancing::RoundRobin);
+        assert_eq!(set.select(&Uri::from_static("/")).unwrap().0, "https://b/");
+        assert_eq!(set.select(&Uri::from_static("/")).unwrap().0, "https://b/");
+        assert!(matches!(EndpointSet::new([]), Err(HttpError::NoEndpoints)));
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use http::Method;
+
+/// Number of recent latencies used to compute percentile delays.
+const WINDOW: usize = 100;
+/// Number of latencies needed before the percentile is used instead of the fixed delay.
+const MIN_SAMPLES: usize = 20;
+/// Maximum number of hedges that can be sent in a burst.
+const MAX_TOKENS: f64 = 10.0;
+
+/// Sends a duplicate of read-only requests whose response head takes too long, using whichever response
+/// arrives first, see [`Connector::with_hedge_policy`](crate::Connector::with_hedge_policy).
+///
+/// The duplicate is sent after a fixed delay, 100ms by default, or at a percentile of recently observed
+/// response head latencies, measured from the first poll of every attempt. To limit the extra load, every
+/// attempt adds the hedge budget, 10% by default, to a balance from which every hedge takes one request,
+/// allowing bursts of up to 10 hedges. Clones share their latency statistics and budget.
+///
+/// Only `GET`, `HEAD` and `OPTIONS` requests without body are hedged, other requests are sent once: a body
+/// is read while it is sent, so it cannot be sent by both requests at the same time, even if it could be
+/// rewound.
+#[derive(Clone)]
+pub struct HedgePolicy {
+    delay: Duration,
+    percentile: Option<f64>,
+    budget: f64,
+    state: Arc<Mutex<HedgeState>>,
+}
+
+struct HedgeState {
+    latencies: VecDeque<Latency>,
+    tokens: f64,
+}
+
+/// Response head latency of the original request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Latency {
+    Measured(Duration),
+    /// The request was dropped for its duplicate or timed out, so it would have taken longer.
+    AtLeast(Duration),
+}
+
+/// Marks responses received for the duplicate request, available in the response extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hedged;
+
+impl Default for HedgePolicy {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(100),
+            percentile: None,
+            budget: 0.1,
+            state: Arc::new(Mutex::new(HedgeState {
+                latencies: VecDeque::with_capacity(WINDOW),
+                tokens: MAX_TOKENS,
+            })),
+        }
+    }
+}
+
+impl HedgePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+    /// Hedges once the response head takes longer than `percentile` (e.g. `0.95`) of the recently observed
+    /// latencies. The fixed delay is used until enough latencies have been observed.
+    pub fn with_percentile_delay(mut self, percentile: f64) -> Self {
+        self.percentile = Some(percentile.clamp(0.0, 1.0));
+        self
+    }
+    /// Fraction of requests that may be hedged, e.g. `0.05` for 5%.
+    pub fn with_budget(mut self, budget: f64) -> Self {
+        self.budget = budget.max(0.0);
+        self
+    }
+
+    /// Whether requests with `method` and a body of `body_len` bytes are hedged.
+    pub(crate) fn allows(&self, method: &Method, body_len: u64) -> bool {
+        matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) && body_len == 0
+    }
+
+    /// Adds the budget of an attempt, returning after how long to hedge it.
+    pub(crate) fn start(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        state.tokens = (state.tokens + self.budget).min(MAX_TOKENS);
+        self.current_delay(&state)
+    }
+
+    /// Requests whose duplicate responded first rank above all measured latencies, as they would have
+    /// taken longer than the delay they were hedged after. Only sampling the faster response would bias
+    /// the percentile towards the latency of hedged requests and lower the delay with every hedge.
+    fn current_delay(&self, state: &HedgeState) -> Duration {
+        match self.percentile {
+            Some(percentile) if state.latencies.len() >= MIN_SAMPLES => {
+                let measured = |latency: &Latency| match latency {
+                    Latency::Measured(latency) => Some(*latency),
+                    Latency::AtLeast(_) => None,
+                };
+                let longest = state.latencies.iter().filter_map(measured).max().unwrap_or_default();
+                let mut latencies: Vec<_> = state
+                    .latencies
+                    .iter()
+                    .map(|latency| match *latency {
+                        Latency::Measured(latency) => (false, latency),
+                        Latency::AtLeast(latency) => (true, latency.max(longest)),
+                    })
+                    .collect();
+                latencies.sort_unstable();
+                let index = ((latencies.len() - 1) as f64 * percentile).round() as usize;
+                latencies[index].1
+            }
+            _ => self.delay,
+        }
+    }
+
+    pub(crate) fn try_take_token(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.tokens < 1.0 {
+            return false;
+        }
+        state.tokens -= 1.0;
+        true
+    }
+
+    pub(crate) fn record(&self, latency: Latency) {
+        let mut state = self.state.lock().unwrap();
+        if state.latencies.len() == WINDOW {
+            state.latencies.pop_front();
+        }
+        state.latencies.push_back(latency);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use futures::executor::block_on;
+    use http::{Method, Request};
+
+    use super::{HedgePolicy, Hedged, Latency};
+    use crate::test_util::{read_head, respond, serve};
+    use crate::{Connector, EndpointSet, RequestWithoutBodyExt};
+
+    fn current_delay(policy: &HedgePolicy) -> Duration {
+        policy.current_delay(&policy.state.lock().unwrap())
+    }
+
+    #[test]
+    fn percentile_delay_and_budget() {
+        let policy = HedgePolicy::new().with_percentile_delay(0.9).with_budget(0.5);
+        assert_eq!(current_delay(&policy), Duration::from_millis(100));
+        for millis in 1..=100 {
+            policy.record(Latency::Measured(Duration::from_millis(millis)));
+        }
+        assert_eq!(current_delay(&policy), Duration::from_millis(90));
+        policy.state.lock().unwrap().tokens = 1.5;
+        assert!(policy.try_take_token());
+        assert!(!policy.try_take_token());
+        assert!(policy.allows(&Method::GET, 0));
+        assert!(!policy.allows(&Method::GET, 4));
+        assert!(!policy.allows(&Method::POST, 0));
+    }
+
+    #[test]
+    fn hedged_latencies_rank_last() {
+        let policy = HedgePolicy::new().with_percentile_delay(0.9);
+        for millis in 1..=80 {
+            policy.record(Latency::Measured(Duration::from_millis(millis)));
+        }
+        for _ in 0..20 {
+            policy.record(Latency::AtLeast(Duration::from_millis(50)));
+        }
+        assert_eq!(current_delay(&policy), Duration::from_millis(80));
+    }
+
+    #[test]
+    fn faster_duplicate_wins() {
+        let (dropped, closed) = mpsc::channel();
+        let slow = serve(move |mut stream| {
+            read_head(&mut stream);
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let _ = dropped.send(matches!(stream.read(&mut [0]), Ok(0)));
+        });
+        let requests = Arc::new(AtomicU32::new(0));
+        let counter = requests.clone();
+        let fast = serve(move |mut stream| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            respond(&mut stream, "fast");
+        });
+        let endpoints = EndpointSet::new([slow, fast].map(|addr| format!("http://{}", addr).parse().unwrap())).unwrap();
+        let policy = HedgePolicy::new().with_delay(Duration::from_millis(50));
+        let connector = Connector::default()
+            .with_endpoint_set("backend", endpoints)
+            .with_hedge_policy(policy.clone());
+
+        let start = Instant::now();
+        let request = Request::get("http://backend/").body(()).unwrap();
+        let mut response = block_on(request.send_with_connector((), connector)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(response.extensions().get::<Hedged>(), Some(&Hedged));
+        assert_eq!(block_on(response.body_mut().string(None)).unwrap(), "fast");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(closed.recv_timeout(Duration::from_secs(5)).unwrap());
+
+        let latencies = &policy.state.lock().unwrap().latencies;
+        assert!(matches!(latencies.iter().collect::<Vec<_>>()[..], [Latency::AtLeast(latency)] if *latency >= Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn delay_starts_on_first_poll() {
+        let requests = Arc::new(AtomicU32::new(0));
+        let counter = requests.clone();
+        let addr = serve(move |mut stream| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            respond(&mut stream, "");
+        });
+        let connector = Connector::default().with_hedge_policy(HedgePolicy::new().with_delay(Duration::from_millis(200)));
+        let request = Request::get(format!("http://{}/", addr)).body(()).unwrap();
+        let send = request.send_with_connector((), connector);
+        thread::sleep(Duration::from_millis(300));
+        let response = block_on(send).unwrap();
+        assert_eq!(response.extensions().get::<Hedged>(), None);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
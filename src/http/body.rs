@@ -1,18 +1,187 @@
-use futures::future::Either;
+use std::borrow::Cow;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future::{poll_fn, Either};
 use futures::io::{empty, Cursor, Empty};
-use futures::AsyncRead;
+use futures::{ready, AsyncRead, AsyncSeek};
+
+use super::error::HttpError;
 
 pub trait IntoRequestBody {
     type RequestBody: AsyncRead + Send;
     fn into_request_body(self) -> (Self::RequestBody, u64);
+    /// Converts into a [`RequestBody`]. Unless overridden, the body can only be sent once.
+    fn into_body<'a>(self) -> RequestBody<'a>
+    where
+        Self: Sized + 'a,
+    {
+        let (reader, len) = self.into_request_body();
+        RequestBody::streaming(reader, len)
+    }
+}
+
+/// Request body that can be rewound to send a request again, e.g. when retrying it.
+///
+/// In-memory bodies can always be rewound, seekable readers are rewound by seeking back to where reading
+/// started. Streaming bodies can only be sent again if none of them has been read yet, unless they
+/// buffer what has been read.
+pub struct RequestBody<'a> {
+    kind: Kind<'a>,
+    len: u64,
+}
+
+enum Kind<'a> {
+    Bytes(Cursor<Cow<'a, [u8]>>),
+    Seekable {
+        reader: Pin<Box<dyn AsyncReadSeek + Send + 'a>>,
+        consumed: u64,
+    },
+    Buffered {
+        reader: Pin<Box<dyn AsyncRead + Send + 'a>>,
+        buffer: Option<Vec<u8>>,
+        position: usize,
+        limit: usize,
+    },
+    Streaming {
+        reader: Pin<Box<dyn AsyncRead + Send + 'a>>,
+        consumed: bool,
+    },
+}
+
+trait AsyncReadSeek: AsyncRead + AsyncSeek {}
+
+impl<T: AsyncRead + AsyncSeek> AsyncReadSeek for T {}
+
+impl<'a> RequestBody<'a> {
+    pub fn empty() -> Self {
+        Self::from_bytes(&[][..])
+    }
+    pub fn from_bytes(bytes: impl Into<Cow<'a, [u8]>>) -> Self {
+        let bytes = bytes.into();
+        let len = bytes.len() as u64;
+        Self {
+            kind: Kind::Bytes(Cursor::new(bytes)),
+            len,
+        }
+    }
+    /// Reads `len` bytes starting at the current position of `reader`, e.g. a file.
+    pub fn seekable(reader: impl AsyncRead + AsyncSeek + Send + 'a, len: u64) -> Self {
+        Self {
+            kind: Kind::Seekable {
+                reader: Box::pin(reader),
+                consumed: 0,
+            },
+            len,
+        }
+    }
+    /// Reads `len` bytes from `reader`, keeping up to `limit` of them to be able to send them again.
+    /// Bodies longer than `limit` cannot be rewound once more than `limit` bytes have been read.
+    pub fn buffered(reader: impl AsyncRead + Send + 'a, len: u64, limit: usize) -> Self {
+        Self {
+            kind: Kind::Buffered {
+                reader: Box::pin(reader),
+                buffer: Some(Vec::new()),
+                position: 0,
+                limit,
+            },
+            len,
+        }
+    }
+    /// Reads `len` bytes from `reader`, which can only be rewound if nothing has been read yet.
+    pub fn streaming(reader: impl AsyncRead + Send + 'a, len: u64) -> Self {
+        Self {
+            kind: Kind::Streaming {
+                reader: Box::pin(reader),
+                consumed: false,
+            },
+            len,
+        }
+    }
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Whether [`Self::rewind`] can succeed.
+    pub fn is_rewindable(&self) -> bool {
+        match &self.kind {
+            Kind::Bytes(_) | Kind::Seekable { .. } => true,
+            Kind::Buffered { buffer, .. } => buffer.is_some(),
+            Kind::Streaming { consumed, .. } => !consumed,
+        }
+    }
+    /// Resets the body to its start, failing with [`HttpError::BodyNotRewindable`] if that is impossible.
+    pub async fn rewind(&mut self) -> Result<(), HttpError> {
+        poll_fn(|cx| self.poll_rewind(cx)).await
+    }
+    pub fn poll_rewind(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), HttpError>> {
+        match &mut self.kind {
+            Kind::Bytes(cursor) => cursor.set_position(0),
+            Kind::Seekable { reader, consumed } => {
+                if *consumed > 0 {
+                    let offset = -i64::try_from(*consumed).map_err(|_| HttpError::BodyNotRewindable)?;
+                    ready!(reader.as_mut().poll_seek(cx, SeekFrom::Current(offset))).map_err(|err| HttpError::IoError(err.into()))?;
+                    *consumed = 0;
+                }
+            }
+            Kind::Buffered {
+                buffer: Some(_), position, ..
+            } => *position = 0,
+            Kind::Buffered { buffer: None, .. } | Kind::Streaming { consumed: true, .. } => return Poll::Ready(Err(HttpError::BodyNotRewindable)),
+            Kind::Streaming { consumed: false, .. } => {}
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for RequestBody<'_> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match &mut self.kind {
+            Kind::Bytes(cursor) => Pin::new(cursor).poll_read(cx, buf),
+            Kind::Seekable { reader, consumed } => {
+                let n = ready!(reader.as_mut().poll_read(cx, buf))?;
+                *consumed += n as u64;
+                Poll::Ready(Ok(n))
+            }
+            Kind::Buffered {
+                reader,
+                buffer,
+                position,
+                limit,
+            } => {
+                if let Some(buffered) = buffer.as_ref().and_then(|buffer| buffer.get(*position..)).filter(|rest| !rest.is_empty()) {
+                    let n = buffered.len().min(buf.len());
+                    buf[..n].copy_from_slice(&buffered[..n]);
+                    *position += n;
+                    return Poll::Ready(Ok(n));
+                }
+                let n = ready!(reader.as_mut().poll_read(cx, buf))?;
+                match buffer {
+                    Some(buffered) if buffered.len() + n <= *limit => buffered.extend_from_slice(&buf[..n]),
+                    _ => *buffer = None,
+                }
+                *position += n;
+                Poll::Ready(Ok(n))
+            }
+            Kind::Streaming { reader, consumed } => {
+                let n = ready!(reader.as_mut().poll_read(cx, buf))?;
+                *consumed |= n > 0;
+                Poll::Ready(Ok(n))
+            }
+        }
+    }
 }
 
 pub trait IntoNonUnitRequestBody: IntoRequestBody {}
 
-impl<'a, T: AsRef<[u8]>> IntoNonUnitRequestBody for &'a T {}
+impl<T: AsRef<[u8]>> IntoNonUnitRequestBody for &T {}
 impl<T: AsyncRead + Send> IntoNonUnitRequestBody for (T, u64) {}
 impl IntoNonUnitRequestBody for Vec<u8> {}
 impl IntoNonUnitRequestBody for String {}
+impl IntoNonUnitRequestBody for RequestBody<'_> {}
 impl<T: IntoNonUnitRequestBody> IntoNonUnitRequestBody for Option<T> {}
 
 impl<T: AsyncRead + Send> IntoRequestBody for (T, u64) {
@@ -28,6 +197,12 @@ impl<'a, T: AsRef<[u8]>> IntoRequestBody for &'a T {
         let slice = self.as_ref();
         (slice, slice.len() as u64)
     }
+    fn into_body<'b>(self) -> RequestBody<'b>
+    where
+        Self: 'b,
+    {
+        RequestBody::from_bytes(self.as_ref())
+    }
 }
 
 impl IntoRequestBody for Vec<u8> {
@@ -36,6 +211,9 @@ impl IntoRequestBody for Vec<u8> {
         let len = self.len() as u64;
         (Cursor::new(self), len)
     }
+    fn into_body<'a>(self) -> RequestBody<'a> {
+        RequestBody::from_bytes(self)
+    }
 }
 
 impl IntoRequestBody for String {
@@ -44,6 +222,9 @@ impl IntoRequestBody for String {
         let len = self.len() as u64;
         (Cursor::new(self), len)
     }
+    fn into_body<'a>(self) -> RequestBody<'a> {
+        RequestBody::from_bytes(self.into_bytes())
+    }
 }
 
 impl IntoRequestBody for () {
@@ -51,6 +232,23 @@ impl IntoRequestBody for () {
     fn into_request_body(self) -> (Self::RequestBody, u64) {
         (empty(), 0)
     }
+    fn into_body<'a>(self) -> RequestBody<'a> {
+        RequestBody::empty()
+    }
+}
+
+impl<'a> IntoRequestBody for RequestBody<'a> {
+    type RequestBody = Self;
+    fn into_request_body(self) -> (Self::RequestBody, u64) {
+        let len = self.len;
+        (self, len)
+    }
+    fn into_body<'b>(self) -> RequestBody<'b>
+    where
+        Self: 'b,
+    {
+        self
+    }
 }
 
 impl<T: IntoRequestBody> IntoRequestBody for Option<T> {
@@ -64,13 +262,88 @@ impl<T: IntoRequestBody> IntoRequestBody for Option<T> {
             }
         }
     }
+    fn into_body<'a>(self) -> RequestBody<'a>
+    where
+        Self: 'a,
+    {
+        match self {
+            None => RequestBody::empty(),
+            Some(v) => v.into_body(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use crate::prelude::*;
-    use http::Request;
+    use crate::test_util::{read_head, serve};
+    use crate::{Attempts, Connector, HttpError, RequestBody, RetryPolicy};
+    use futures::io::Cursor;
+    use futures::AsyncReadExt;
+    use http::{Request, StatusCode};
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    async fn read(body: &mut RequestBody<'_>) -> Vec<u8> {
+        let mut data = Vec::new();
+        body.read_to_end(&mut data).await.unwrap();
+        data
+    }
+
+    #[test]
+    fn test_rewind() {
+        futures::executor::block_on(async {
+            let mut bodies = [
+                RequestBody::from_bytes(&b"body"[..]),
+                RequestBody::seekable(Cursor::new(b"body"), 4),
+                RequestBody::buffered(Cursor::new(b"body"), 4, 4),
+            ];
+            for body in &mut bodies {
+                assert_eq!(read(body).await, b"body");
+                body.rewind().await.unwrap();
+                assert_eq!(read(body).await, b"body");
+            }
+            let mut body = RequestBody::buffered(Cursor::new(b"body"), 4, 3);
+            assert_eq!(read(&mut body).await, b"body");
+            assert!(matches!(body.rewind().await, Err(HttpError::BodyNotRewindable)));
+            let mut body = RequestBody::streaming(Cursor::new(b"body"), 4);
+            body.rewind().await.unwrap();
+            assert_eq!(read(&mut body).await, b"body");
+            assert!(matches!(body.rewind().await, Err(HttpError::BodyNotRewindable)));
+        })
+    }
+
+    #[test]
+    fn test_retry_sends_same_body() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let bodies = received.clone();
+        let addr = serve(move |mut stream| {
+            read_head(&mut stream);
+            let mut body = [0; 9];
+            stream.read_exact(&mut body).unwrap();
+            let mut bodies = bodies.lock().unwrap();
+            bodies.push(body.to_vec());
+            let response: &[u8] = match bodies.len() % 2 {
+                1 => b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                _ => b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            };
+            stream.write_all(response).unwrap();
+        });
+        let connector = Connector::default().with_retry_policy(RetryPolicy::new().with_backoff(Duration::ZERO, Duration::ZERO));
+        let mut seekable = Cursor::new(b"skip seekable");
+        seekable.set_position(4);
+        let bodies = [RequestBody::seekable(seekable, 9), RequestBody::buffered(Cursor::new(b"buffered!"), 9, 9)];
+        for body in bodies {
+            let request = Request::put(format!("http://{}/", addr)).body(()).unwrap();
+            let response = futures::executor::block_on(request.send_with_connector(body, connector.clone())).unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.extensions().get::<Attempts>(), Some(&Attempts(2)));
+        }
+        let received = received.lock().unwrap();
+        assert_eq!(*received, [&b" seekable"[..], b" seekable", b"buffered!", b"buffered!"]);
+    }
 
     #[test]
     fn test_send_with_async_read_body() {
@@ -1,20 +1,114 @@
+use std::sync::Arc;
+
 use http::{
     uri::{Authority, Scheme},
     HeaderMap, Uri,
 };
+use idna::AsciiDenyList;
 
 use super::error::HttpError;
 
 pub(crate) fn extract_origin(uri: &Uri, headers: &HeaderMap) -> Result<(Option<Scheme>, String, Option<u16>), HttpError> {
     if let Some(auth) = uri.authority() {
-        return Ok((uri.scheme().cloned(), auth.host().to_string(), auth.port_u16()));
+        return Ok((uri.scheme().cloned(), host_to_ascii(auth.host())?, auth.port_u16()));
     }
     if let Some(header) = headers.get(http::header::HOST) {
         if let Ok(auth) = Authority::try_from(header.as_bytes()) {
             if auth.as_str().len() == auth.host().len() + 1usize + auth.port().map(|p| p.as_str().len()).unwrap_or(0) {
-                return Ok((None, auth.host().to_string(), auth.port_u16()));
+                return Ok((None, host_to_ascii(auth.host())?, auth.port_u16()));
+            }
+        } else if let Ok(header) = std::str::from_utf8(header.as_bytes()) {
+            if !header.is_ascii() {
+                let (host, port) = split_port(header)?;
+                return Ok((None, host_to_ascii(host)?, port));
             }
         }
     }
     Err(HttpError::MissingHost)
 }
+
+/// Parses a URI whose host may contain Unicode labels, converting the host to its
+/// ASCII-compatible encoding (punycode) first. `http::Uri` only accepts ASCII hosts.
+pub fn parse_uri(uri: &str) -> Result<Uri, HttpError> {
+    if uri.is_ascii() {
+        return Uri::try_from(uri).map_err(|err| HttpError::InvalidUri(Arc::new(err)));
+    }
+    let authority_start = uri.find("://").map(|i| i + 3).unwrap_or(0);
+    let authority_end = uri[authority_start..]
+        .find(['/', '?', '#'])
+        .map(|i| i + authority_start)
+        .unwrap_or(uri.len());
+    let authority = &uri[authority_start..authority_end];
+    let host_start = authority.rfind('@').map(|i| i + 1).unwrap_or(0);
+    let (host, port) = split_port(&authority[host_start..])?;
+    let mut converted = String::with_capacity(uri.len());
+    converted.push_str(&uri[..authority_start + host_start]);
+    converted.push_str(&host_to_ascii(host)?);
+    if let Some(port) = port {
+        converted.push_str(&format!(":{}", port));
+    }
+    converted.push_str(&uri[authority_end..]);
+    Uri::try_from(converted).map_err(|err| HttpError::InvalidUri(Arc::new(err)))
+}
+
+/// Converts a host to its ASCII-compatible encoding using UTS #46 processing, which also
+/// validates and normalizes hosts that are already ASCII. IPv6 literals are returned unchanged.
+pub(crate) fn host_to_ascii(host: &str) -> Result<String, HttpError> {
+    if host.starts_with('[') {
+        return Ok(host.to_string());
+    }
+    match idna::domain_to_ascii_cow(host.as_bytes(), AsciiDenyList::URL) {
+        Ok(ascii) if !ascii.is_empty() => Ok(ascii.into_owned()),
+        _ => Err(HttpError::InvalidHost(host.to_string())),
+    }
+}
+
+/// Converts a configured host name like [`host_to_ascii`], keeping invalid names lowercased so they match no
+/// request.
+pub(crate) fn normalize_host(host: &str) -> String {
+    host_to_ascii(host).unwrap_or_else(|_| host.to_ascii_lowercase())
+}
+
+fn split_port(host_and_port: &str) -> Result<(&str, Option<u16>), HttpError> {
+    match host_and_port.rsplit_once(':') {
+        Some((host, port)) if !host.starts_with('[') || host.ends_with(']') => match port.parse() {
+            Ok(port) => Ok((host, Some(port))),
+            Err(_) => Err(HttpError::InvalidHost(host_and_port.to_string())),
+        },
+        _ => Ok((host_and_port, None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_parse_uri_idna() {
+        let uri = parse_uri("https://user@bücher.example:8443/straße?q=ü").unwrap();
+        assert_eq!(uri.host(), Some("xn--bcher-kva.example"));
+        assert_eq!(uri.port_u16(), Some(8443));
+        assert!(matches!(parse_uri("https://xn--a.ü/"), Err(HttpError::InvalidHost(_))));
+    }
+
+    #[test]
+    fn test_extract_origin_idna() {
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::HOST, HeaderValue::from_bytes("bücher.example:8080".as_bytes()).unwrap());
+        let (_, host, port) = extract_origin(&Uri::from_static("/path"), &headers).unwrap();
+        assert_eq!(host, "xn--bcher-kva.example");
+        assert_eq!(port, Some(8080));
+        let (_, host, _) = extract_origin(&Uri::from_static("https://Example.COM/"), &HeaderMap::new()).unwrap();
+        assert_eq!(host, "example.com");
+    }
+
+    #[test]
+    fn test_host_to_ascii() {
+        assert_eq!(host_to_ascii("xn--bcher-kva.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(host_to_ascii("127.0.0.1").unwrap(), "127.0.0.1");
+        assert_eq!(host_to_ascii("[::1]").unwrap(), "[::1]");
+        assert!(matches!(host_to_ascii("xn--a.example"), Err(HttpError::InvalidHost(_))));
+        assert!(matches!(host_to_ascii("exa mple.com"), Err(HttpError::InvalidHost(_))));
+    }
+}
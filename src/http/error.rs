@@ -1,9 +1,12 @@
 use std::{io, sync::Arc};
 
-use http::{uri::Scheme, HeaderValue, Method};
+use http::{
+    uri::{InvalidUri, Scheme},
+    HeaderValue, Method,
+};
 use thiserror::Error;
 
-use crate::TransportError;
+use crate::{PolicyViolation, TimeoutPhase, TransportError};
 
 #[derive(Error, Debug, Clone)]
 pub enum HttpError {
@@ -17,6 +20,12 @@ pub enum HttpError {
     #[error("missing host in URI or host header")]
     MissingHost,
     #[cfg(not(target_arch = "wasm32"))]
+    #[error("invalid host name: {0:?}")]
+    InvalidHost(String),
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("invalid uri: {0:?}")]
+    InvalidUri(Arc<InvalidUri>),
+    #[cfg(not(target_arch = "wasm32"))]
     #[error("unexpected URI scheme: {0:?}")]
     UnexpectedScheme(Scheme),
     #[cfg(not(target_arch = "wasm32"))]
@@ -26,6 +35,30 @@ pub enum HttpError {
     #[error("connect error: {0:?}")]
     ConnectError(TransportError),
     #[cfg(not(target_arch = "wasm32"))]
+    #[error("outbound policy violation: {0}")]
+    PolicyViolation(PolicyViolation),
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("{0} timeout")]
+    Timeout(TimeoutPhase),
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("circuit of {0} is open")]
+    CircuitOpen(String),
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("request queue of {0} is full")]
+    QueueFull(String),
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("endpoint set without endpoints")]
+    NoEndpoints,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("request body cannot be rewound to send the request again")]
+    BodyNotRewindable,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("aborted")]
+    Aborted,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("connector is shutting down")]
+    ShuttingDown,
+    #[cfg(not(target_arch = "wasm32"))]
     #[error("io error: {0:?}")]
     IoError(Arc<io::Error>),
 }
@@ -37,12 +70,28 @@ impl From<HttpError> for io::Error {
             HttpError::InvalidMethod(_) => io::ErrorKind::InvalidData,
             HttpError::Redirect => io::ErrorKind::Unsupported,
             HttpError::MissingHost => io::ErrorKind::Unsupported,
+            HttpError::InvalidHost(_) => io::ErrorKind::InvalidData,
+            HttpError::InvalidUri(_) => io::ErrorKind::InvalidData,
             HttpError::UnexpectedScheme(_) => io::ErrorKind::Unsupported,
             HttpError::ConnectError(err) => match err {
                 TransportError::InvalidDnsName(_) => io::ErrorKind::InvalidData,
                 TransportError::TcpConnect(err) => err.kind(),
+                TransportError::SrvLookup(_, err) => err.kind(),
                 TransportError::TlsConnect(err) => err.kind(),
+                TransportError::PinMismatch(_) => io::ErrorKind::InvalidData,
+                TransportError::EchRejected => io::ErrorKind::InvalidData,
+                TransportError::EchRetryConfig(_) => io::ErrorKind::InvalidData,
+                TransportError::PolicyViolation(_) => io::ErrorKind::PermissionDenied,
+                TransportError::Timeout(_) => io::ErrorKind::TimedOut,
             },
+            HttpError::Timeout(_) => io::ErrorKind::TimedOut,
+            HttpError::PolicyViolation(_) => io::ErrorKind::PermissionDenied,
+            HttpError::CircuitOpen(_) => io::ErrorKind::ConnectionRefused,
+            HttpError::QueueFull(_) => io::ErrorKind::WouldBlock,
+            HttpError::NoEndpoints => io::ErrorKind::InvalidInput,
+            HttpError::BodyNotRewindable => io::ErrorKind::Unsupported,
+            HttpError::Aborted => io::ErrorKind::ConnectionAborted,
+            HttpError::ShuttingDown => io::ErrorKind::ConnectionRefused,
             HttpError::IoError(err) => err.kind(),
             HttpError::UnsupportedTransferEncoding(_) => io::ErrorKind::Unsupported,
         };
@@ -1,12 +1,19 @@
 use self::body::IntoNonUnitRequestBody;
-pub use self::body::IntoRequestBody;
+pub use self::body::{IntoRequestBody, RequestBody};
+pub use self::common::parse_uri;
+pub(crate) use self::common::{extract_origin, normalize_host};
 pub use self::error::HttpError;
+use crate::hedge::Latency;
+use crate::lifecycle::{InFlight, Lifecycle};
+use crate::{abort::AbortListener, AbortHandle, Attempts, Connector, HedgePolicy, Hedged};
+use async_io::Timer;
 use futures::{future::FusedFuture, ready, AsyncRead, AsyncReadExt, Future};
 use futures_rustls::rustls::ClientConfig;
 use serde::de::DeserializeOwned;
 use std::fmt::{Debug, Formatter};
 use std::io::ErrorKind::InvalidData;
 use std::sync::Arc;
+use std::time::Instant;
 use std::{
     io,
     pin::Pin,
@@ -18,25 +25,35 @@ mod error;
 mod request_native;
 mod response_native;
 
-type RequestSendInner<'a> = request_native::RequestSend<'a>;
+type RequestSendInner = request_native::RequestSend;
 
 pub trait RequestWithBodyExt<'a>: Sized {
     type B: IntoNonUnitRequestBody;
     #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
     fn send(self) -> RequestSend<'a> {
-        let client_config = crate::DEFAULT_CLIENT_CONFIG.clone();
-        self.send_with_client_config(client_config)
+        self.send_with_connector(Connector::default())
     }
-    fn send_with_client_config(self, client_config: Arc<ClientConfig>) -> RequestSend<'a>;
+    /// Sends the request with a [`Connector`] using `client_config` and default settings otherwise.
+    fn send_with_client_config(self, client_config: Arc<ClientConfig>) -> RequestSend<'a> {
+        self.send_with_connector(Connector::new(client_config))
+    }
+    /// Sends the request with the settings of `connector`. Implementations provide this method rather than
+    /// `send_with_client_config`, which used to be the required one.
+    fn send_with_connector(self, connector: Connector) -> RequestSend<'a>;
 }
 
 pub trait RequestWithoutBodyExt<'a>: Sized {
     #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
     fn send<B: IntoRequestBody + 'a>(&self, body: B) -> RequestSend<'a> {
-        let client_config = crate::DEFAULT_CLIENT_CONFIG.clone();
-        self.send_with_client_config(body, client_config)
+        self.send_with_connector(body, Connector::default())
     }
-    fn send_with_client_config<B: IntoRequestBody + 'a>(&self, body: B, client_config: Arc<ClientConfig>) -> RequestSend<'a>;
+    /// Sends the request with a [`Connector`] using `client_config` and default settings otherwise.
+    fn send_with_client_config<B: IntoRequestBody + 'a>(&self, body: B, client_config: Arc<ClientConfig>) -> RequestSend<'a> {
+        self.send_with_connector(body, Connector::new(client_config))
+    }
+    /// Sends the request with the settings of `connector`. Implementations provide this method rather than
+    /// `send_with_client_config`, which used to be the required one.
+    fn send_with_connector<B: IntoRequestBody + 'a>(&self, body: B, connector: Connector) -> RequestSend<'a>;
 }
 
 pub trait RequestExt {
@@ -54,18 +71,48 @@ impl<B> RequestExt for http::Request<B> {
 
 impl<'a, T: IntoNonUnitRequestBody + 'a> RequestWithBodyExt<'a> for http::Request<T> {
     type B = T;
-    fn send_with_client_config(self, client_config: Arc<ClientConfig>) -> RequestSend<'a> {
+    fn send_with_connector(self, connector: Connector) -> RequestSend<'a> {
         let (this, body) = self.swap_body(());
-        this.send_with_client_config(body, client_config)
+        this.send_with_connector(body, connector)
     }
 }
 
 impl<'a> RequestWithoutBodyExt<'a> for http::Request<()> {
-    fn send_with_client_config<B: IntoRequestBody + 'a>(&self, body: B, client_config: Arc<ClientConfig>) -> RequestSend<'a> {
-        let (read, len) = body.into_request_body();
-        let body: (Pin<Box<dyn AsyncRead + Send>>, _) = (Box::pin(read), len);
-        let inner = RequestSendInner::new_with_client_config(self.clone(), body, client_config);
-        RequestSend { inner }
+    fn send_with_connector<B: IntoRequestBody + 'a>(&self, body: B, connector: Connector) -> RequestSend<'a> {
+        let body = body.into_body();
+        let retry = match connector.retry_policy() {
+            Some(policy) if policy.allows_method(self.method()) => Some(Box::new(Retry {
+                request: self.clone(),
+                connector: connector.clone(),
+                backoff: None,
+                rewinding: false,
+            })),
+            _ => None,
+        };
+        let hedge = match connector.hedge_policy() {
+            Some(policy) if policy.allows(self.method(), body.len()) => Some(Box::new(Hedge {
+                policy: policy.clone(),
+                request: self.clone(),
+                connector: connector.clone(),
+                timer: None,
+                inner: None,
+                error: None,
+            })),
+            _ => None,
+        };
+        let abort = self.extensions().get::<AbortHandle>().map(AbortHandle::listen).into_iter().collect();
+        let lifecycle = Some(connector.lifecycle().clone());
+        let inner = RequestSendInner::new_with_connector(self.clone(), body.len(), connector);
+        RequestSend {
+            inner,
+            body,
+            retry,
+            hedge,
+            attempts: 0,
+            abort,
+            lifecycle,
+            in_flight: None,
+        }
     }
 }
 
@@ -73,21 +120,186 @@ pub struct RequestSend<'a>
 where
     Self: Send,
 {
-    inner: RequestSendInner<'a>,
+    inner: RequestSendInner,
+    body: RequestBody<'a>,
+    retry: Option<Box<Retry>>,
+    hedge: Option<Box<Hedge>>,
+    attempts: u32,
+    abort: Vec<AbortListener>,
+    /// Tracks the request as in flight once it is first polled.
+    lifecycle: Option<Lifecycle>,
+    in_flight: Option<InFlight>,
+}
+
+/// What is needed to send a request again.
+struct Retry {
+    request: http::Request<()>,
+    connector: Connector,
+    backoff: Option<Timer>,
+    rewinding: bool,
+}
+
+/// Duplicate of the current attempt, sent once its response head takes longer than the hedge delay.
+struct Hedge {
+    policy: HedgePolicy,
+    request: http::Request<()>,
+    connector: Connector,
+    /// Started on the first poll of an attempt.
+    timer: Option<(Instant, Timer)>,
+    inner: Option<RequestSendInner>,
+    /// Error of the attempt, returned if the duplicate fails too.
+    error: Option<HttpError>,
+}
+
+impl Hedge {
+    /// Polls the attempt `primary` and its duplicate, returning the first response. The slower request is
+    /// aborted, closing its connection.
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        primary: &mut RequestSendInner,
+        body: &mut RequestBody,
+    ) -> Poll<Result<http::Response<ResponseBodyInner>, HttpError>> {
+        let (start, timer) = self.timer.get_or_insert_with(|| (Instant::now(), Timer::after(self.policy.start())));
+        let start = *start;
+        if !primary.is_terminated() {
+            if let Poll::Ready(result) = primary.poll(cx, body) {
+                match &result {
+                    Ok(_) => self.policy.record(Latency::Measured(start.elapsed())),
+                    Err(HttpError::Timeout(_)) => self.policy.record(Latency::AtLeast(start.elapsed())),
+                    Err(_) => {}
+                }
+                match result {
+                    Err(err) if self.inner.is_some() => self.error = Some(err),
+                    // the attempt failed before hedging, retries are up to the retry policy
+                    result => return self.finish(result),
+                }
+            }
+        }
+        if self.inner.is_none() && !primary.is_terminated() && Pin::new(timer).poll(cx).is_ready() && self.policy.try_take_token() {
+            log::debug!("hedging request to {} after {:?}", self.request.uri(), start.elapsed());
+            self.inner = Some(RequestSendInner::new_with_connector(self.request.clone(), 0, self.connector.clone()));
+        }
+        let Some(inner) = &mut self.inner else { return Poll::Pending };
+        match ready!(inner.poll(cx, &mut RequestBody::empty())) {
+            Ok(mut response) => {
+                if !primary.is_terminated() {
+                    primary.abort();
+                    self.policy.record(Latency::AtLeast(start.elapsed()));
+                }
+                response.extensions_mut().insert(Hedged);
+                self.finish(Ok(response))
+            }
+            Err(err) => {
+                self.inner = None;
+                match primary.is_terminated() {
+                    true => {
+                        let err = self.error.take().unwrap_or(err);
+                        self.finish(Err(err))
+                    }
+                    false => Poll::Pending,
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, result: Result<http::Response<ResponseBodyInner>, HttpError>) -> Poll<Result<http::Response<ResponseBodyInner>, HttpError>> {
+        self.reset();
+        Poll::Ready(result)
+    }
+
+    /// Resets the hedge for the next attempt, aborting the duplicate if it is still in flight.
+    fn reset(&mut self) {
+        if let Some(mut inner) = self.inner.take() {
+            inner.abort();
+        }
+        (self.timer, self.error) = (None, None);
+    }
 }
 
 impl Future for RequestSend<'_> {
     type Output = Result<http::Response<ResponseBody>, HttpError>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let response = ready!(self.inner.poll(cx))?;
+        let this = &mut *self;
+        if let Some(lifecycle) = this.lifecycle.take() {
+            let (in_flight, abort) = lifecycle.start().inspect_err(|_| this.inner.abort())?;
+            this.in_flight = Some(in_flight);
+            this.abort.push(abort);
+        }
+        let result = match this.abort.iter().any(|abort| abort.poll_aborted(cx).is_ready()) {
+            true => {
+                this.inner.abort();
+                if let Some(hedge) = &mut this.hedge {
+                    hedge.reset();
+                }
+                this.retry = None;
+                Err(HttpError::Aborted)
+            }
+            false => ready!(this.poll_attempts(cx)),
+        };
+        let in_flight = this.in_flight.take();
+        let mut response = result?;
+        response.body_mut().abort_with(std::mem::take(&mut this.abort));
+        if let Some(in_flight) = in_flight {
+            response.body_mut().hold_until_read(in_flight);
+        }
         Ok(response.map(|inner| ResponseBody { inner })).into()
     }
 }
 
+impl RequestSend<'_> {
+    /// Sends the request, retrying it as allowed by the retry policy.
+    fn poll_attempts(&mut self, cx: &mut Context<'_>) -> Poll<Result<http::Response<ResponseBodyInner>, HttpError>> {
+        loop {
+            if let Some(retry) = &mut self.retry {
+                if let Some(backoff) = &mut retry.backoff {
+                    ready!(Pin::new(backoff).poll(cx));
+                    retry.backoff = None;
+                    retry.rewinding = true;
+                }
+                if retry.rewinding {
+                    ready!(self.body.poll_rewind(cx))?;
+                    retry.rewinding = false;
+                    self.inner = RequestSendInner::new_with_connector(retry.request.clone(), self.body.len(), retry.connector.clone());
+                }
+            }
+            let result = match &mut self.hedge {
+                Some(hedge) => ready!(hedge.poll(cx, &mut self.inner, &mut self.body)),
+                None => ready!(self.inner.poll(cx, &mut self.body)),
+            };
+            self.attempts += 1;
+            if let Some(retry) = &mut self.retry {
+                let policy = retry.connector.retry_policy().unwrap();
+                match policy.retry_delay(retry.request.method(), self.attempts, &result) {
+                    Some(_) if !self.body.is_rewindable() => {
+                        log::debug!("cannot retry request to {}, body is not rewindable", retry.request.uri());
+                    }
+                    Some(delay) => {
+                        log::debug!(
+                            "retrying request to {} in {:?} after attempt {}",
+                            retry.request.uri(),
+                            delay,
+                            self.attempts
+                        );
+                        retry.backoff = Some(Timer::after(delay));
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+            let mut response = result?;
+            response.extensions_mut().insert(Attempts(self.attempts));
+            return Poll::Ready(Ok(response));
+        }
+    }
+}
+
 impl FusedFuture for RequestSend<'_> {
     fn is_terminated(&self) -> bool {
         self.inner.is_terminated()
+            && self.hedge.as_ref().is_none_or(|hedge| hedge.inner.is_none())
+            && self.retry.as_ref().is_none_or(|retry| retry.backoff.is_none() && !retry.rewinding)
     }
 }
 
@@ -97,6 +309,11 @@ pub struct ResponseBody {
     inner: ResponseBodyInner,
 }
 
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ResponseBody>();
+};
+
 #[cfg(feature = "websocket")]
 impl ResponseBody {
     pub(crate) fn into_inner(self) -> Result<(async_http_codec::BodyDecodeState, crate::Transport), HttpError> {
@@ -5,45 +5,88 @@ use std::mem::replace;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Instant;
 
 use async_http_codec::internal::buffer_decode::BufferDecodeState;
 use async_http_codec::internal::buffer_write::BufferWriteState;
 use async_http_codec::internal::io_future::{IoFutureState, IoFutureWithOutputState};
 use async_http_codec::{BodyEncodeState, RequestHead, ResponseHead};
+use async_io::Timer;
 
-use futures::{AsyncRead, AsyncWrite, Future};
+use futures::{ready, AsyncRead, AsyncWrite, Future};
 
 use http::uri::{PathAndQuery, Scheme};
 use http::{HeaderMap, HeaderValue, Method, Response, Uri, Version};
 
-use crate::{ClientConfig, Transport, TransportError};
+use crate::circuit::CircuitPermit;
+use crate::concurrency::{ConcurrencyPermit, Queue, QueueTime};
+use crate::endpoint::EndpointGuard;
+use crate::rate_limit::RateLimiter;
+use crate::srv::{srv_scheme, SrvTarget, SrvTargets};
+use crate::timeout::{earliest, with_timeout, PhaseTimer};
+use crate::{ConnectionInfo, Connector, TimeoutPhase, Timeouts, Transport, TransportError};
 
+use super::body::RequestBody;
 use super::common::extract_origin;
 use super::error::HttpError;
 use super::response_native::ResponseBodyInner;
 
-pub(crate) enum RequestSend<'a> {
+pub(crate) struct RequestSend {
+    state: State,
+    body_len: u64,
+    timeouts: Timeouts,
+    deadline: Option<Instant>,
+    head_deadline: Option<Instant>,
+    timer: PhaseTimer,
+    early_data: bool,
+    circuit: Option<CircuitPermit>,
+    rate_limit: Option<(RateLimiter, String)>,
+    permit: Option<(ConcurrencyPermit, QueueTime)>,
+    endpoint: Option<EndpointGuard>,
+    srv: Option<(SrvTargets, Connector)>,
+}
+
+enum State {
     Start {
-        body: (Pin<Box<dyn AsyncRead + Send + 'a>>, u64),
         method: Method,
         uri: Uri,
         headers: HeaderMap,
-        client_config: Arc<ClientConfig>,
+        connector: Connector,
+    },
+    Resolving {
+        lookup: Pin<Box<dyn Future<Output = Result<Vec<SrvTarget>, TransportError>> + Send>>,
+        https: bool,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        connector: Connector,
+    },
+    Queued {
+        queue: Queue,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        connector: Connector,
+    },
+    Throttled {
+        timer: Timer,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        connector: Connector,
     },
     PendingConnect {
-        body: (Pin<Box<dyn AsyncRead + Send + 'a>>, u64),
         method: Method,
         uri: Uri,
         headers: HeaderMap,
         transport: Pin<Box<dyn Future<Output = Result<Transport, TransportError>> + Send>>,
     },
     SendingHead {
-        body: (Pin<Box<dyn AsyncRead + Send + 'a>>, u64),
         write_state: BufferWriteState,
         transport: Transport,
     },
     SendingBody {
-        body: (Pin<Box<dyn AsyncRead + Send + 'a>>, u64),
+        remaining: u64,
         buffer: (Vec<u8>, usize, usize),
         write_state: Box<BodyEncodeState>,
         transport: Transport,
@@ -53,39 +96,122 @@ pub(crate) enum RequestSend<'a> {
     },
     ReceivingHead {
         transport: Transport,
+        info: ConnectionInfo,
         dec_state: BufferDecodeState<ResponseHead<'static>>,
     },
     Finished,
 }
 
-impl RequestSend<'_> {
-    pub fn new_with_client_config<'a>(
-        request: http::Request<()>,
-        body: (Pin<Box<dyn AsyncRead + Send + 'a>>, u64),
-        client_config: Arc<ClientConfig>,
-    ) -> RequestSend<'a> {
+impl RequestSend {
+    /// Sends `request` with a body of `body_len` bytes, which is passed to every poll.
+    pub fn new_with_connector(request: http::Request<()>, body_len: u64, connector: Connector) -> RequestSend {
         let uri = request.uri().clone();
         let headers = request.headers().clone();
         let method = request.method().clone();
-        RequestSend::Start {
-            method,
-            body,
-            uri,
-            headers,
-            client_config,
+        let timeouts = *connector.timeouts();
+        RequestSend {
+            state: State::Start {
+                method,
+                uri,
+                headers,
+                connector,
+            },
+            body_len,
+            timeouts,
+            deadline: None,
+            head_deadline: None,
+            timer: PhaseTimer::default(),
+            early_data: false,
+            circuit: None,
+            rate_limit: None,
+            permit: None,
+            endpoint: None,
+            srv: None,
         }
     }
-    pub fn poll(&mut self, cx: &mut Context) -> Poll<Result<http::Response<ResponseBodyInner>, HttpError>> {
+    pub fn poll(&mut self, cx: &mut Context, body: &mut RequestBody) -> Poll<Result<http::Response<ResponseBodyInner>, HttpError>> {
+        let mut result = ready!(self.poll_deadlines(cx, body));
+        if let Some(circuit) = self.circuit.take() {
+            circuit.record(&result);
+        }
+        if let Some(endpoint) = self.endpoint.take() {
+            endpoint.record(&result);
+            if let Ok(response) = &mut result {
+                response.body_mut().hold_until_read(endpoint);
+            }
+        }
+        if let (Some((limiter, key)), Ok(response)) = (self.rate_limit.take(), &result) {
+            limiter.update(&key, response.status(), response.headers());
+        }
+        if let (Some((permit, queue_time)), Ok(response)) = (self.permit.take(), &mut result) {
+            response.extensions_mut().insert(queue_time);
+            response.body_mut().hold_until_read(permit);
+        }
+        Poll::Ready(result)
+    }
+    fn poll_deadlines(&mut self, cx: &mut Context, body: &mut RequestBody) -> Poll<Result<http::Response<ResponseBodyInner>, HttpError>> {
+        if self.deadline.is_none() {
+            self.deadline = self.timeouts.total.map(|total| Instant::now() + total);
+        }
+        match self.poll_state(cx, body) {
+            Poll::Ready(Ok(mut response)) => {
+                response.body_mut().set_timeouts(self.deadline, self.timeouts.body_idle);
+                Poll::Ready(Ok(response))
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => {
+                if self.head_deadline.is_none() && matches!(self.state, State::ReceivingHead { .. }) {
+                    self.head_deadline = self.timeouts.response_head.map(|timeout| Instant::now() + timeout);
+                }
+                let deadline = earliest(
+                    self.deadline.map(|deadline| (TimeoutPhase::Total, deadline)),
+                    self.head_deadline.map(|deadline| (TimeoutPhase::ResponseHead, deadline)),
+                );
+                match self.timer.poll(cx, deadline) {
+                    Poll::Ready(phase) => {
+                        self.state = State::Finished;
+                        Poll::Ready(Err(HttpError::Timeout(phase)))
+                    }
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+        }
+    }
+    fn poll_state(&mut self, cx: &mut Context, body: &mut RequestBody) -> Poll<Result<http::Response<ResponseBodyInner>, HttpError>> {
         loop {
-            let s = replace(self, RequestSend::Finished);
+            let s = replace(&mut self.state, State::Finished);
             match s {
-                RequestSend::Start {
+                State::Start {
                     method,
-                    body,
-                    uri,
+                    mut uri,
                     headers,
-                    client_config,
+                    connector,
                 } => {
+                    if let Some(https) = srv_scheme(uri.scheme_str()) {
+                        let name = uri.host().ok_or(HttpError::MissingHost)?.to_string();
+                        let resolver = connector.srv_resolver().clone();
+                        let timeout = connector.timeouts().connect;
+                        self.state = State::Resolving {
+                            lookup: Box::pin(async move {
+                                with_timeout(timeout, TimeoutPhase::Connect, resolver.resolve(&name))
+                                    .await
+                                    .map_err(TransportError::Timeout)?
+                            }),
+                            https,
+                            method,
+                            uri,
+                            headers,
+                            connector,
+                        };
+                        continue;
+                    }
+                    if self.endpoint.is_none() {
+                        if let Some(endpoint_set) = connector.endpoint_set(&extract_origin(&uri, &headers)?.1) {
+                            let (rewritten, endpoint) = endpoint_set.select(&uri)?;
+                            uri = rewritten;
+                            self.endpoint = Some(endpoint);
+                        }
+                    }
                     let (scheme, host, port) = extract_origin(&uri, &headers)?;
                     let https = match scheme {
                         _ if scheme == Some(Scheme::HTTP) => false,
@@ -93,21 +219,173 @@ impl RequestSend<'_> {
                         None => true,
                         Some(scheme) => return Poll::Ready(Err(HttpError::UnexpectedScheme(scheme))),
                     };
-                    let https = https.then_some(client_config);
                     let port = port.unwrap_or(match https {
-                        Some(_) => 443,
-                        None => 80,
+                        true => 443,
+                        false => 80,
                     });
-                    *self = RequestSend::PendingConnect {
-                        body,
-                        transport: Box::pin(async move { Transport::connect(https, &host, port).await }),
+                    if let Some(policy) = connector.policy() {
+                        let scheme = if https { Scheme::HTTPS } else { Scheme::HTTP };
+                        policy.check_origin(&scheme, &host, port).map_err(HttpError::PolicyViolation)?;
+                    }
+                    if let (Some(limiter), None) = (connector.rate_limiter(), &self.rate_limit) {
+                        let (key, delay) = limiter.reserve(&host, uri.path());
+                        self.rate_limit = Some((limiter.clone(), key));
+                        if let Some(delay) = delay {
+                            self.state = State::Throttled {
+                                timer: Timer::after(delay),
+                                method,
+                                uri,
+                                headers,
+                                connector,
+                            };
+                            continue;
+                        }
+                    }
+                    let origin = format!("{}://{}:{}", if https { "https" } else { "http" }, host, port);
+                    if let (Some(limiter), None) = (connector.concurrency_limiter(), &self.permit) {
+                        self.state = State::Queued {
+                            queue: limiter.acquire(origin),
+                            method,
+                            uri,
+                            headers,
+                            connector,
+                        };
+                        continue;
+                    }
+                    if let Some(breaker) = connector.circuit_breaker() {
+                        match breaker.acquire(origin.clone()) {
+                            Ok(circuit) => self.circuit = Some(circuit),
+                            // an open circuit is treated like a failed connection to the srv target
+                            Err(err) => match &mut self.srv {
+                                Some((srv, _)) => match srv.next(&uri) {
+                                    Some(next) => {
+                                        log::debug!("circuit of {} is open, trying the next srv target", origin);
+                                        self.state = State::Start {
+                                            method,
+                                            uri: next?,
+                                            headers,
+                                            connector,
+                                        };
+                                        continue;
+                                    }
+                                    None => {
+                                        srv.invalidate();
+                                        return Poll::Ready(Err(err));
+                                    }
+                                },
+                                None => return Poll::Ready(Err(err)),
+                            },
+                        }
+                    }
+                    if let Some(transport) = connector.lifecycle().take_preconnected(&connector.preconnect_key(origin.clone(), &host)) {
+                        log::debug!("using preconnected connection to {}", origin);
+                        self.early_data = false;
+                        self.state = State::PendingConnect {
+                            transport: Box::pin(async move { Ok(transport) }),
+                            method,
+                            uri,
+                            headers,
+                        };
+                        continue;
+                    }
+                    // early data may be replayed, so only use it for safe requests
+                    let early_data = connector.early_data() && self.body_len == 0 && matches!(method, Method::GET | Method::HEAD | Method::OPTIONS);
+                    self.early_data = early_data;
+                    self.state = State::PendingConnect {
+                        transport: Box::pin(async move { Transport::connect(connector, https, &host, port, early_data).await }),
                         method,
                         uri,
                         headers,
                     }
                 }
-                RequestSend::PendingConnect {
-                    body,
+                State::Resolving {
+                    mut lookup,
+                    https,
+                    method,
+                    uri,
+                    headers,
+                    connector,
+                } => match lookup.as_mut().poll(cx) {
+                    Poll::Ready(Ok(targets)) => {
+                        let name = uri.host().unwrap_or_default().to_ascii_lowercase();
+                        let mut srv = SrvTargets::new(connector.srv_resolver().clone(), name, https, targets);
+                        let uri = srv.next(&uri).ok_or(HttpError::NoEndpoints)??;
+                        self.srv = Some((srv, connector.clone()));
+                        self.state = State::Start {
+                            method,
+                            uri,
+                            headers,
+                            connector,
+                        }
+                    }
+                    Poll::Ready(Err(TransportError::Timeout(phase))) => return Poll::Ready(Err(HttpError::Timeout(phase))),
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::ConnectError(err))),
+                    Poll::Pending => {
+                        self.state = State::Resolving {
+                            lookup,
+                            https,
+                            method,
+                            uri,
+                            headers,
+                            connector,
+                        };
+                        return Poll::Pending;
+                    }
+                },
+                State::Queued {
+                    mut queue,
+                    method,
+                    uri,
+                    headers,
+                    connector,
+                } => match Pin::new(&mut queue).poll(cx) {
+                    Poll::Ready(result) => {
+                        self.permit = Some(result?);
+                        self.state = State::Start {
+                            method,
+                            uri,
+                            headers,
+                            connector,
+                        }
+                    }
+                    Poll::Pending => {
+                        self.state = State::Queued {
+                            queue,
+                            method,
+                            uri,
+                            headers,
+                            connector,
+                        };
+                        return Poll::Pending;
+                    }
+                },
+                State::Throttled {
+                    mut timer,
+                    method,
+                    uri,
+                    headers,
+                    connector,
+                } => match Pin::new(&mut timer).poll(cx) {
+                    Poll::Ready(_) => {
+                        self.state = State::Start {
+                            method,
+                            uri,
+                            headers,
+                            connector,
+                        }
+                    }
+                    Poll::Pending => {
+                        self.state = State::Throttled {
+                            timer,
+                            method,
+                            uri,
+                            headers,
+                            connector,
+                        };
+                        return Poll::Pending;
+                    }
+                },
+                State::PendingConnect {
                     mut transport,
                     method,
                     uri,
@@ -116,11 +394,11 @@ impl RequestSend<'_> {
                     Poll::Ready(Ok(transport)) => {
                         let (_scheme, host, port) = extract_origin(&uri, &headers)?;
                         let mut path_and_query = uri.path_and_query().cloned().unwrap_or_else(|| PathAndQuery::from_static("/"));
-                        if path_and_query == PathAndQuery::from_static("") {
+                        if path_and_query.as_str().is_empty() {
                             path_and_query = PathAndQuery::from_static("/");
                         }
                         let mut head = RequestHead::new(method, Cow::Owned(path_and_query.into()), Version::HTTP_11, Cow::Borrowed(&headers));
-                        if head.headers().get(http::header::HOST).is_none() {
+                        if !head.headers().get(http::header::HOST).is_some_and(|host| host.as_bytes().is_ascii()) {
                             let host = match port {
                                 Some(port) => HeaderValue::from_str(&format!("{}:{}", host, port)).unwrap(),
                                 None => HeaderValue::from_str(&host).unwrap(),
@@ -128,20 +406,41 @@ impl RequestSend<'_> {
                             head.headers_mut().insert(http::header::HOST, host);
                         }
                         if head.headers().get(http::header::CONTENT_LENGTH).is_none() {
-                            let length = HeaderValue::from_str(&format!("{}", body.1)).unwrap();
+                            let length = HeaderValue::from_str(&format!("{}", self.body_len)).unwrap();
                             head.headers_mut().insert(http::header::CONTENT_LENGTH, length);
                         }
                         let write_state = head.encode_state();
-                        *self = RequestSend::SendingHead {
-                            write_state,
-                            transport,
-                            body,
-                        };
+                        self.state = State::SendingHead { write_state, transport };
+                    }
+                    Poll::Ready(Err(err)) => {
+                        let connect_failure = matches!(
+                            err,
+                            TransportError::TcpConnect(_) | TransportError::TlsConnect(_) | TransportError::Timeout(_)
+                        );
+                        if let (Some((srv, connector)), true) = (&mut self.srv, connect_failure) {
+                            if let Some(next) = srv.next(&uri) {
+                                log::debug!("connecting to {} failed, trying the next srv target: {}", uri, err);
+                                if let Some(circuit) = self.circuit.take() {
+                                    circuit.record::<()>(&Err(HttpError::ConnectError(err)));
+                                }
+                                self.state = State::Start {
+                                    method,
+                                    uri: next?,
+                                    headers,
+                                    connector: connector.clone(),
+                                };
+                                continue;
+                            }
+                            srv.invalidate();
+                        }
+                        return Poll::Ready(Err(match err {
+                            TransportError::PolicyViolation(violation) => HttpError::PolicyViolation(violation),
+                            TransportError::Timeout(phase) => HttpError::Timeout(phase),
+                            err => HttpError::ConnectError(err),
+                        }));
                     }
-                    Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::ConnectError(err))),
                     Poll::Pending => {
-                        *self = RequestSend::PendingConnect {
-                            body,
+                        self.state = State::PendingConnect {
                             method,
                             uri,
                             headers,
@@ -150,59 +449,54 @@ impl RequestSend<'_> {
                         return Poll::Pending;
                     }
                 },
-                RequestSend::SendingHead {
+                State::SendingHead {
                     mut write_state,
                     mut transport,
-                    body,
                 } => match write_state.poll(cx, &mut transport) {
                     Poll::Ready(Ok(())) => {
-                        let write_state = BodyEncodeState::new(Some(body.1));
-                        *self = RequestSend::SendingBody {
+                        let write_state = BodyEncodeState::new(Some(self.body_len));
+                        self.state = State::SendingBody {
                             buffer: (vec![0u8; 1 << 14], 0, 0),
-                            body,
+                            remaining: self.body_len,
                             write_state: Box::new(write_state),
                             transport,
                         }
                     }
                     Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::IoError(Arc::new(err)))),
                     Poll::Pending => {
-                        *self = RequestSend::SendingHead {
-                            write_state,
-                            transport,
-                            body,
-                        };
+                        self.state = State::SendingHead { write_state, transport };
                         return Poll::Pending;
                     }
                 },
-                RequestSend::SendingBody {
+                State::SendingBody {
                     mut buffer,
                     mut write_state,
                     mut transport,
-                    mut body,
+                    mut remaining,
                 } => {
                     if buffer.2 == 0 {
-                        if body.1 == 0 {
-                            *self = RequestSend::Flushing { transport }
+                        if remaining == 0 {
+                            self.state = State::Flushing { transport }
                         } else {
-                            let max = (buffer.0.len() as u64).min(body.1) as usize;
-                            match body.0.as_mut().poll_read(cx, &mut buffer.0[0..max]) {
+                            let max = (buffer.0.len() as u64).min(remaining) as usize;
+                            match Pin::new(&mut *body).poll_read(cx, &mut buffer.0[0..max]) {
                                 Poll::Ready(Ok(0)) => return Poll::Ready(Err(HttpError::IoError(Arc::new(UnexpectedEof.into())))),
                                 Poll::Ready(Ok(n)) => {
                                     buffer.2 = n;
-                                    *self = RequestSend::SendingBody {
+                                    self.state = State::SendingBody {
                                         buffer,
                                         write_state,
                                         transport,
-                                        body,
+                                        remaining,
                                     };
                                 }
                                 Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::IoError(Arc::new(err)))),
                                 Poll::Pending => {
-                                    *self = RequestSend::SendingBody {
+                                    self.state = State::SendingBody {
                                         buffer,
                                         write_state,
                                         transport,
-                                        body,
+                                        remaining,
                                     };
                                     return Poll::Pending;
                                 }
@@ -211,26 +505,26 @@ impl RequestSend<'_> {
                     } else {
                         match write_state.poll_write(&mut transport, cx, &buffer.0[buffer.1..buffer.2]) {
                             Poll::Ready(Ok(n)) => {
-                                body.1 -= n as u64;
+                                remaining -= n as u64;
                                 buffer.1 += n;
                                 if buffer.1 == buffer.2 {
                                     buffer.1 = 0;
                                     buffer.2 = 0;
                                 }
 
-                                *self = RequestSend::SendingBody {
+                                self.state = State::SendingBody {
                                     write_state,
                                     transport,
-                                    body,
+                                    remaining,
                                     buffer,
                                 }
                             }
                             Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::IoError(Arc::new(err)))),
                             Poll::Pending => {
-                                *self = RequestSend::SendingBody {
+                                self.state = State::SendingBody {
                                     write_state,
                                     transport,
-                                    body,
+                                    remaining,
                                     buffer,
                                 };
                                 return Poll::Pending;
@@ -238,37 +532,55 @@ impl RequestSend<'_> {
                         }
                     }
                 }
-                RequestSend::Flushing { mut transport } => match Pin::new(&mut transport).poll_flush(cx) {
+                State::Flushing { mut transport } => match Pin::new(&mut transport).poll_flush(cx) {
                     Poll::Ready(Ok(())) => {
+                        // with early data the tls handshake is only guaranteed to be complete after flushing
+                        let info = ConnectionInfo::new(&transport, self.early_data);
                         let dec_state = ResponseHead::decode_state();
-                        *self = RequestSend::ReceivingHead { dec_state, transport }
+                        self.state = State::ReceivingHead { dec_state, transport, info }
                     }
                     Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::IoError(Arc::new(err)))),
                     Poll::Pending => {
-                        *self = RequestSend::Flushing { transport };
+                        self.state = State::Flushing { transport };
                         return Poll::Pending;
                     }
                 },
-                RequestSend::ReceivingHead {
+                State::ReceivingHead {
                     mut dec_state,
                     mut transport,
+                    info,
                 } => match dec_state.poll(cx, &mut transport) {
                     Poll::Ready(Ok(head)) => {
                         let body = ResponseBodyInner::new(transport, &head)?;
-                        let parts: http::response::Parts = head.into();
+                        let mut parts: http::response::Parts = head.into();
+                        parts.extensions.insert(info);
                         return Poll::Ready(Ok(Response::from_parts(parts, body)));
                     }
                     Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::IoError(Arc::new(err)))),
                     Poll::Pending => {
-                        *self = RequestSend::ReceivingHead { transport, dec_state };
+                        self.state = State::ReceivingHead { transport, info, dec_state };
                         return Poll::Pending;
                     }
                 },
-                RequestSend::Finished => panic!("polled finished future"),
+                State::Finished => panic!("polled finished future"),
             }
         }
     }
+    /// Stops the request, closing its connection without recording an outcome.
+    pub fn abort(&mut self) {
+        match replace(&mut self.state, State::Finished) {
+            State::SendingHead { transport, .. }
+            | State::SendingBody { transport, .. }
+            | State::Flushing { transport }
+            | State::ReceivingHead { transport, .. } => transport.close_now(),
+            _ => {}
+        }
+        self.circuit = None;
+        self.endpoint = None;
+        self.permit = None;
+        self.srv = None;
+    }
     pub fn is_terminated(&self) -> bool {
-        matches!(self, RequestSend::Finished)
+        matches!(self.state, State::Finished)
     }
 }
@@ -2,13 +2,16 @@ use std::{
     io,
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use async_http_codec::{BodyDecodeState, ResponseHead};
 use futures::AsyncRead;
 use http::HeaderValue;
 
-use crate::Transport;
+use crate::abort::AbortListener;
+use crate::timeout::{earliest, PhaseTimer};
+use crate::{TimeoutPhase, Transport};
 
 use super::error::HttpError;
 
@@ -16,6 +19,12 @@ pub struct ResponseBodyInner {
     state: BodyDecodeState,
     transport: Option<Transport>,
     error: Option<HttpError>,
+    deadline: Option<Instant>,
+    idle_timeout: Option<Duration>,
+    idle_deadline: Option<Instant>,
+    timer: PhaseTimer,
+    held: Vec<Box<dyn Send + Sync>>,
+    abort: Vec<AbortListener>,
 }
 
 impl ResponseBodyInner {
@@ -27,11 +36,29 @@ impl ResponseBodyInner {
             state,
             transport: Some(transport),
             error: None,
+            deadline: None,
+            idle_timeout: None,
+            idle_deadline: None,
+            timer: PhaseTimer::default(),
+            held: Vec::new(),
+            abort: Vec::new(),
         })
     }
+    pub(crate) fn set_timeouts(&mut self, deadline: Option<Instant>, idle_timeout: Option<Duration>) {
+        self.deadline = deadline;
+        self.idle_timeout = idle_timeout;
+    }
+    /// Keeps `guard`, e.g. a permit counting the request as in flight, until the body has been read or dropped.
+    pub(crate) fn hold_until_read(&mut self, guard: impl Send + Sync + 'static) {
+        self.held.push(Box::new(guard));
+    }
+    /// Fails reads with [`HttpError::Aborted`] and closes the connection once one of `abort` fires.
+    pub(crate) fn abort_with(&mut self, abort: Vec<AbortListener>) {
+        self.abort = abort;
+    }
     #[cfg(feature = "websocket")]
     pub(crate) fn into_inner(self) -> Result<(BodyDecodeState, Transport), HttpError> {
-        let ResponseBodyInner { state, transport, error } = self;
+        let ResponseBodyInner { state, transport, error, .. } = self;
         if let Some(err) = error {
             return Err(err);
         }
@@ -44,16 +71,52 @@ impl AsyncRead for ResponseBodyInner {
         if let Some(err) = &self.error {
             return Poll::Ready(Err(err.clone().into()));
         }
-        let mut transport = self.transport.take().unwrap();
+        if self.abort.iter().any(|abort| abort.poll_aborted(cx).is_ready()) {
+            if let Some(transport) = self.transport.take() {
+                transport.close_now();
+            }
+            self.held.clear();
+            self.error = Some(HttpError::Aborted);
+            return Poll::Ready(Err(HttpError::Aborted.into()));
+        }
+        let Some(mut transport) = self.transport.take() else {
+            return Poll::Ready(Ok(0));
+        };
         match self.state.poll_read(&mut transport, cx, buf) {
             Poll::Ready(Err(err)) => {
                 // TODO: Return HeaderValue in upstream error
                 self.error = Some(HttpError::IoError(err.into()));
                 Poll::Ready(Err(self.error.clone().unwrap().into()))
             }
-            p => {
+            Poll::Ready(Ok(n)) => {
+                self.idle_deadline = None;
+                match n == 0 && !buf.is_empty() {
+                    // connections are not reused, so close it right away, sending TLS close_notify
+                    true => {
+                        transport.close_now();
+                        self.held.clear();
+                    }
+                    false => self.transport = Some(transport),
+                }
+                Poll::Ready(Ok(n))
+            }
+            Poll::Pending => {
                 self.transport = Some(transport);
-                p
+                if self.idle_deadline.is_none() {
+                    self.idle_deadline = self.idle_timeout.map(|timeout| Instant::now() + timeout);
+                }
+                let deadline = earliest(
+                    self.deadline.map(|deadline| (TimeoutPhase::Total, deadline)),
+                    self.idle_deadline.map(|deadline| (TimeoutPhase::BodyIdle, deadline)),
+                );
+                match self.timer.poll(cx, deadline) {
+                    Poll::Ready(phase) => {
+                        self.transport = None;
+                        self.error = Some(HttpError::Timeout(phase));
+                        Poll::Ready(Err(self.error.clone().unwrap().into()))
+                    }
+                    Poll::Pending => Poll::Pending,
+                }
             }
         }
     }
@@ -1,51 +1,150 @@
+mod abort;
+mod circuit;
+mod concurrency;
+mod connection_info;
+mod connector;
+mod date;
+mod dns;
+mod endpoint;
+mod hedge;
 mod http;
+mod lifecycle;
+mod policy;
 pub mod prelude;
+mod rate_limit;
+mod retry;
+mod socket;
+mod srv;
+#[cfg(test)]
+mod test_util;
+mod timeout;
+mod tls;
 #[cfg(feature = "websocket")]
 mod ws;
 
 use std::{
     io,
-    net::IpAddr,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
 };
 
+pub use crate::abort::AbortHandle;
+pub use crate::circuit::{CircuitBreaker, CircuitState};
+pub use crate::concurrency::{ConcurrencyLimiter, QueueTime};
+pub use crate::connection_info::{ConnectionInfo, TlsInfo};
+pub use crate::connector::Connector;
+pub use crate::endpoint::{EndpointSet, LoadBalancing};
+pub use crate::hedge::{HedgePolicy, Hedged};
 pub use crate::http::*;
+pub use crate::policy::{IpNet, OutboundPolicy, PolicyViolation};
+pub use crate::rate_limit::{RateLimit, RateLimiter};
+pub use crate::retry::{Attempts, RetryCause, RetryPolicy};
+pub use crate::socket::{Keepalive, SocketOptions};
+pub use crate::srv::{SrvResolver, SrvTarget};
+pub use crate::timeout::{TimeoutPhase, Timeouts};
+#[cfg(feature = "dangerous-insecure-tls")]
+pub use crate::tls::DangerousVerification;
+pub use crate::tls::{CertificatePins, CertificateRevocation, ClientAuth, PinMismatch, SpkiPin, TlsConfigBuilder, TlsConfigError};
 use async_net::TcpStream;
 use futures::{AsyncRead, AsyncWrite};
 use futures_rustls::{client::TlsStream, rustls::ClientConfig, TlsConnector};
 use rustls_pki_types::{InvalidDnsNameError, ServerName};
+use timeout::with_timeout;
 #[cfg(feature = "websocket")]
 pub use ws::*;
 
+#[allow(clippy::large_enum_variant)]
 pub enum Transport {
     Tcp(TcpStream),
     Tls(TlsStream<TcpStream>),
 }
 
 impl Transport {
-    async fn connect(tls: Option<Arc<ClientConfig>>, host: &str, port: u16) -> Result<Self, TransportError> {
+    async fn connect(connector: Connector, https: bool, host: &str, port: u16, early_data: bool) -> Result<Self, TransportError> {
         let server = ServerName::try_from(host)
             .map_err(|err| TransportError::InvalidDnsName(Arc::new(err)))?
             .to_owned();
-        let tcp = match &server {
-            ServerName::DnsName(name) => TcpStream::connect((name.as_ref(), port)).await,
-            ServerName::IpAddress(ip) => TcpStream::connect((IpAddr::from(*ip), port)).await,
-            _ => unreachable!(),
+        let tcp = Self::connect_tcp(&connector, host, port).await?;
+        if !https {
+            return Ok(Transport::Tcp(tcp));
         }
-        .map_err(|err| TransportError::TcpConnect(Arc::new(err)))?;
-        let transport = match tls {
-            None => Transport::Tcp(tcp),
-            Some(client_config) => {
-                let tls = TlsConnector::from(client_config)
-                    .connect(server, tcp)
-                    .await
-                    .map_err(|err| TransportError::TlsConnect(Arc::new(err)))?;
-                Transport::Tls(tls)
+        let client_config = connector.client_config_for_host(host);
+        let err = match Self::handshake(&connector, client_config, server.clone(), tcp, early_data).await? {
+            Ok(tls) => return Ok(Transport::Tls(tls)),
+            Err(err) => err,
+        };
+        #[cfg(feature = "aws-lc-rs")]
+        match tls::EchRejection::from_io_error(&err) {
+            Some(tls::EchRejection::Retry(retry_configs)) => {
+                let client_config = match connector.retry_ech(host, retry_configs) {
+                    Some(client_config) => client_config.map_err(TransportError::EchRetryConfig)?,
+                    None => return Err(TransportError::EchRejected),
+                };
+                log::debug!("retrying connection to {} with ech retry configs", host);
+                let tcp = Self::connect_tcp(&connector, host, port).await?;
+                return match Self::handshake(&connector, client_config, server, tcp, early_data).await? {
+                    Ok(tls) => Ok(Transport::Tls(tls)),
+                    Err(err) => Err(Self::tls_error(err)),
+                };
             }
+            Some(tls::EchRejection::NoRetry) => return Err(TransportError::EchRejected),
+            None => {}
+        }
+        Err(Self::tls_error(err))
+    }
+
+    async fn connect_tcp(connector: &Connector, host: &str, port: u16) -> Result<TcpStream, TransportError> {
+        with_timeout(connector.timeouts().connect, TimeoutPhase::Connect, connector.connect_tcp(host, port))
+            .await
+            .map_err(TransportError::Timeout)?
+    }
+
+    async fn handshake(
+        connector: &Connector,
+        client_config: Arc<ClientConfig>,
+        server: ServerName<'static>,
+        tcp: TcpStream,
+        early_data: bool,
+    ) -> Result<io::Result<TlsStream<TcpStream>>, TransportError> {
+        let tls_connector = TlsConnector::from(client_config);
+        #[cfg(feature = "early-data")]
+        let tls_connector = tls_connector.early_data(early_data);
+        #[cfg(not(feature = "early-data"))]
+        let _ = early_data;
+        let handshake = tls_connector.connect(server, tcp);
+        with_timeout(connector.timeouts().tls_handshake, TimeoutPhase::TlsHandshake, handshake)
+            .await
+            .map_err(TransportError::Timeout)
+    }
+
+    /// Closes the connection without waiting, sending TLS `close_notify` unless the socket buffer is full.
+    pub(crate) fn close_now(mut self) {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let _ = Pin::new(&mut self).poll_close(&mut cx);
+    }
+
+    /// Whether the peer has not closed the connection, checked without blocking.
+    pub(crate) fn is_open(&self) -> bool {
+        let tcp = match self {
+            Transport::Tcp(tcp) => tcp,
+            Transport::Tls(tls) => tls.get_ref().0,
         };
-        Ok(transport)
+        match socket2::SockRef::from(tcp).peek(&mut [std::mem::MaybeUninit::uninit()]) {
+            Ok(n) => n > 0,
+            Err(err) => err.kind() == io::ErrorKind::WouldBlock,
+        }
+    }
+
+    fn tls_error(err: io::Error) -> TransportError {
+        #[cfg(feature = "aws-lc-rs")]
+        if tls::EchRejection::from_io_error(&err).is_some() {
+            return TransportError::EchRejected;
+        }
+        match PinMismatch::from_io_error(&err) {
+            Some(mismatch) => TransportError::PinMismatch(mismatch),
+            None => TransportError::TlsConnect(Arc::new(err)),
+        }
     }
 }
 
@@ -91,36 +190,25 @@ pub enum TransportError {
     InvalidDnsName(Arc<InvalidDnsNameError>),
     #[error("tcp connect error: {0:?}")]
     TcpConnect(Arc<io::Error>),
+    #[error("srv lookup of {0} failed: {1:?}")]
+    SrvLookup(String, Arc<io::Error>),
     #[error("tls connect error: {0:?}")]
     TlsConnect(Arc<io::Error>),
+    #[error("{0}")]
+    PinMismatch(PinMismatch),
+    #[error("server rejected encrypted client hello")]
+    EchRejected,
+    #[error("invalid ech retry configs: {0}")]
+    EchRetryConfig(TlsConfigError),
+    #[error("outbound policy violation: {0}")]
+    PolicyViolation(PolicyViolation),
+    #[error("{0} timeout")]
+    Timeout(TimeoutPhase),
 }
 
 #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
 lazy_static::lazy_static! {
-    pub (crate) static ref DEFAULT_CLIENT_CONFIG: Arc<ClientConfig> = {
-        let roots = webpki_roots::TLS_SERVER_ROOTS
-        .iter()
-        .map(|t| {
-            let t = t.to_owned();
-            rustls_pki_types::TrustAnchor {
-                subject: t.subject,
-                subject_public_key_info: t.subject_public_key_info,
-                name_constraints: t.name_constraints.map(Into::into),
-            }
-        });
-        let mut root_store = futures_rustls::rustls::RootCertStore::empty();
-        root_store.extend(roots);
-        #[cfg(all(feature = "ring", not(feature = "aws-lc-rs")))]
-        let provider = futures_rustls::rustls::crypto::ring::default_provider();
-        #[cfg(feature = "aws-lc-rs")]
-        let provider = futures_rustls::rustls::crypto::aws_lc_rs::default_provider();
-
-        let mut config = ClientConfig::builder_with_provider(Arc::new(provider))
-            .with_safe_default_protocol_versions()
-            .expect("could not enable default TLS versions")
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
-        config.alpn_protocols.push(b"http/1.1".to_vec());
-        Arc::new(config)
-    };
+    pub (crate) static ref DEFAULT_CLIENT_CONFIG: Arc<ClientConfig> = TlsConfigBuilder::new()
+        .build()
+        .expect("could not build default TLS config");
 }
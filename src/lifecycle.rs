@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    future::poll_fn,
+    hash::{Hash, Hasher},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use futures::future::{join_all, select, Either};
+use futures::AsyncWriteExt;
+use futures_rustls::rustls::ClientConfig;
+
+use crate::{abort::AbortListener, AbortHandle, HttpError, OutboundPolicy, SocketOptions, Transport};
+
+/// Requests in flight and preconnected connections of a connector and its clones.
+#[derive(Clone, Default)]
+pub(crate) struct Lifecycle {
+    state: Arc<LifecycleState>,
+}
+
+#[derive(Default)]
+struct LifecycleState {
+    closing: AtomicBool,
+    abort: AbortHandle,
+    in_flight: Mutex<InFlightState>,
+    preconnected: Mutex<HashMap<PreconnectKey, Vec<Transport>>>,
+}
+
+/// Origin and connector settings a preconnected connection was opened with, so it is only used by requests
+/// which would open the same connection. Configs are compared by identity.
+pub(crate) struct PreconnectKey {
+    origin: String,
+    client_config: Arc<ClientConfig>,
+    policy: Option<Arc<OutboundPolicy>>,
+    socket_options: SocketOptions,
+}
+
+#[derive(Default)]
+struct InFlightState {
+    count: usize,
+    idle_wakers: Vec<Waker>,
+}
+
+impl Lifecycle {
+    /// Starts tracking a new request, unless shutting down.
+    pub fn start(&self) -> Result<(InFlight, AbortListener), HttpError> {
+        if self.state.closing.load(Ordering::SeqCst) {
+            return Err(HttpError::ShuttingDown);
+        }
+        Ok(self.track())
+    }
+    /// Tracks an operation which continues one in flight, e.g. a websocket connection upgraded from a request.
+    pub fn track(&self) -> (InFlight, AbortListener) {
+        self.state.in_flight.lock().unwrap().count += 1;
+        (InFlight(self.clone()), self.state.abort.listen())
+    }
+
+    pub fn put_preconnected(&self, key: PreconnectKey, transport: Transport) -> Result<(), HttpError> {
+        let mut preconnected = self.state.preconnected.lock().unwrap();
+        if self.state.closing.load(Ordering::SeqCst) {
+            drop(preconnected);
+            transport.close_now();
+            return Err(HttpError::ShuttingDown);
+        }
+        preconnected.entry(key).or_default().push(transport);
+        Ok(())
+    }
+    /// Takes a preconnected connection for `key`, discarding connections closed by the server.
+    pub fn take_preconnected(&self, key: &PreconnectKey) -> Option<Transport> {
+        let mut preconnected = self.state.preconnected.lock().unwrap();
+        let transports = preconnected.get_mut(key)?;
+        let transport = std::iter::from_fn(|| transports.pop()).find(Transport::is_open);
+        if transports.is_empty() {
+            preconnected.remove(key);
+        }
+        transport
+    }
+
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        self.state.closing.store(true, Ordering::SeqCst);
+        let idle = poll_fn(|cx| {
+            let mut in_flight = self.state.in_flight.lock().unwrap();
+            if in_flight.count == 0 {
+                return Poll::Ready(());
+            }
+            if !in_flight.idle_wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                in_flight.idle_wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        });
+        let finished = matches!(select(pin!(idle), Timer::at(deadline)).await, Either::Left(_));
+        if !finished {
+            let count = self.state.in_flight.lock().unwrap().count;
+            log::debug!("aborting {} requests and websocket connections still in flight", count);
+            self.state.abort.abort();
+        }
+        let preconnected: Vec<Transport> = self
+            .state
+            .preconnected
+            .lock()
+            .unwrap()
+            .drain()
+            .flat_map(|(_, transports)| transports)
+            .collect();
+        // closing is polled first, so connections whose close_notify fits the socket buffer are closed
+        // cleanly even once the deadline has passed
+        let close = join_all(preconnected.into_iter().map(|mut transport| async move {
+            let _ = transport.close().await;
+        }));
+        if let Either::Right(_) = select(close, Timer::at(deadline)).await {
+            log::debug!("preconnected connections not closed before the shutdown deadline");
+        }
+        finished
+    }
+}
+
+impl PreconnectKey {
+    pub fn new(origin: String, client_config: Arc<ClientConfig>, policy: Option<Arc<OutboundPolicy>>, socket_options: SocketOptions) -> Self {
+        Self {
+            origin,
+            client_config,
+            policy,
+            socket_options,
+        }
+    }
+}
+
+impl PartialEq for PreconnectKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.origin == other.origin
+            && Arc::ptr_eq(&self.client_config, &other.client_config)
+            && match (&self.policy, &other.policy) {
+                (Some(policy), Some(other)) => Arc::ptr_eq(policy, other),
+                (policy, other) => policy.is_none() && other.is_none(),
+            }
+            && self.socket_options == other.socket_options
+    }
+}
+
+impl Eq for PreconnectKey {}
+
+impl Hash for PreconnectKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.origin.hash(state);
+        Arc::as_ptr(&self.client_config).hash(state);
+        self.policy.as_ref().map(Arc::as_ptr).hash(state);
+        self.socket_options.hash(state);
+    }
+}
+
+/// Counts a request or websocket connection as in flight until dropped.
+pub(crate) struct InFlight(Lifecycle);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut in_flight = self.0.state.in_flight.lock().unwrap();
+        in_flight.count -= 1;
+        if in_flight.count == 0 {
+            let wakers = std::mem::take(&mut in_flight.idle_wakers);
+            drop(in_flight);
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        task::Context,
+        time::{Duration, Instant},
+    };
+
+    use futures::{executor::block_on, task::noop_waker_ref};
+    use http::Request;
+
+    use super::Lifecycle;
+    use crate::test_util::{respond, serve, serve_tls, server_config, Ca};
+    use crate::{Connector, HttpError, RequestWithoutBodyExt, SocketOptions, TlsConfigBuilder};
+
+    #[test]
+    fn shutdown() {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let lifecycle = Lifecycle::default();
+        let (first, first_abort) = lifecycle.start().unwrap();
+        let (second, second_abort) = lifecycle.start().unwrap();
+        drop(first);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            drop(second);
+        });
+        assert!(block_on(lifecycle.shutdown(Duration::from_secs(5))));
+        assert!(matches!(lifecycle.start(), Err(HttpError::ShuttingDown)));
+        assert!(first_abort.poll_aborted(&mut cx).is_pending());
+        let (_late, late_abort) = lifecycle.track();
+        assert!(!block_on(lifecycle.shutdown(Duration::from_millis(10))));
+        assert!(late_abort.poll_aborted(&mut cx).is_ready() && second_abort.poll_aborted(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn shutdown_closes_preconnected() {
+        let ca = Ca::new("root");
+        let (leaf, key) = ca.leaf("localhost");
+        let (closed, clean) = mpsc::channel();
+        let addr = serve_tls(server_config(vec![leaf], key), move |mut stream| {
+            let _ = closed.send(stream.read_to_end(&mut Vec::new()).is_ok());
+        });
+        let client_config = TlsConfigBuilder::new().with_root_certificate(ca.der()).build().unwrap();
+        let connector = Connector::new(client_config);
+        let uri: http::Uri = format!("https://localhost:{}/", addr.port()).parse().unwrap();
+        block_on(connector.preconnect(&uri)).unwrap();
+        assert!(block_on(connector.shutdown(Duration::from_secs(5))));
+        // reading fails with an unexpected EOF if the connection is closed without close_notify
+        assert!(clean.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+
+    #[test]
+    fn preconnected_used_by_request() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let addr = serve(move |mut stream| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            respond(&mut stream, "ok");
+        });
+        let uri: http::Uri = format!("http://{}/", addr).parse().unwrap();
+        let get = |connector: &Connector| {
+            let request = Request::get(uri.clone()).body(()).unwrap();
+            block_on(async {
+                let mut response = request.send_with_connector((), connector.clone()).await.unwrap();
+                response.body_mut().string(None).await.unwrap()
+            })
+        };
+        let connector = Connector::default();
+        block_on(connector.preconnect(&uri)).unwrap();
+        let started = Instant::now();
+        while connections.load(Ordering::SeqCst) == 0 && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // a clone with different socket options opens its own connection
+        let other = connector.clone().with_socket_options(SocketOptions::new().with_nodelay(true));
+        assert_eq!(get(&other), "ok");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert_eq!(get(&connector.clone()), "ok");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert_eq!(get(&connector), "ok");
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use http::uri::Scheme;
+pub use ipnet::IpNet;
+
+use crate::http::normalize_host;
+
+/// Ranges that are not publicly routable or reach infrastructure of the local network or host,
+/// including loopback, link-local (cloud metadata services), private and reserved ranges.
+const NON_PUBLIC_NETWORKS: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "::1/128",
+    "64:ff9b:1::/48",
+    "100::/64",
+    "2001:db8::/32",
+    "fc00::/7",
+    "fe80::/10",
+    "fec0::/10",
+    "ff00::/8",
+];
+
+/// Restricts which destinations a [`Connector`](crate::Connector) may connect to.
+///
+/// Scheme, port and host rules are checked before connecting. Network rules are checked against
+/// every resolved address right before connecting to it, so a host name resolving to a denied
+/// address is rejected regardless of when or how often it is resolved. Addresses in an allowed
+/// network are exempt from the denied networks.
+#[derive(Clone, Debug, Default)]
+pub struct OutboundPolicy {
+    allowed_schemes: Option<Vec<Scheme>>,
+    allowed_ports: Option<Vec<u16>>,
+    allowed_hosts: Option<Vec<String>>,
+    denied_hosts: Vec<String>,
+    allowed_networks: Vec<IpNet>,
+    denied_networks: Vec<IpNet>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    Scheme(Scheme),
+    Port(u16),
+    Host(String),
+    Address(IpAddr),
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyViolation::Scheme(scheme) => write!(f, "scheme {} is not allowed", scheme),
+            PolicyViolation::Port(port) => write!(f, "port {} is not allowed", port),
+            PolicyViolation::Host(host) => write!(f, "host {} is not allowed", host),
+            PolicyViolation::Address(addr) => write!(f, "address {} is not allowed", addr),
+        }
+    }
+}
+
+impl OutboundPolicy {
+    /// Creates a policy allowing everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates a policy denying loopback, link-local, site-local, private, shared, multicast, documentation
+    /// and reserved addresses, including IPv6 addresses embedding them (IPv4-mapped, IPv4-compatible, NAT64
+    /// and 6to4).
+    pub fn public_only() -> Self {
+        let networks = NON_PUBLIC_NETWORKS.iter().map(|net| net.parse().unwrap());
+        Self::new().with_denied_networks(networks)
+    }
+    pub fn with_allowed_schemes(mut self, schemes: impl IntoIterator<Item = Scheme>) -> Self {
+        self.allowed_schemes.get_or_insert_with(Vec::new).extend(schemes);
+        self
+    }
+    pub fn with_allowed_ports(mut self, ports: impl IntoIterator<Item = u16>) -> Self {
+        self.allowed_ports.get_or_insert_with(Vec::new).extend(ports);
+        self
+    }
+    /// Only allows hosts matching one of the patterns. A pattern is either a host name or `*.` followed
+    /// by a domain, matching all subdomains of that domain.
+    pub fn with_allowed_hosts(mut self, patterns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let patterns = patterns.into_iter().map(|pattern| normalize_pattern(pattern.into()));
+        self.allowed_hosts.get_or_insert_with(Vec::new).extend(patterns);
+        self
+    }
+    /// Denies hosts matching one of the patterns, using the same syntax as [`Self::with_allowed_hosts`].
+    pub fn with_denied_hosts(mut self, patterns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let patterns = patterns.into_iter().map(|pattern| normalize_pattern(pattern.into()));
+        self.denied_hosts.extend(patterns);
+        self
+    }
+    pub fn with_allowed_networks(mut self, networks: impl IntoIterator<Item = IpNet>) -> Self {
+        self.allowed_networks.extend(networks);
+        self
+    }
+    pub fn with_denied_networks(mut self, networks: impl IntoIterator<Item = IpNet>) -> Self {
+        self.denied_networks.extend(networks);
+        self
+    }
+
+    pub(crate) fn check_origin(&self, scheme: &Scheme, host: &str, port: u16) -> Result<(), PolicyViolation> {
+        if let Some(schemes) = &self.allowed_schemes {
+            if !schemes.contains(scheme) {
+                return Err(PolicyViolation::Scheme(scheme.clone()));
+            }
+        }
+        if let Some(ports) = &self.allowed_ports {
+            if !ports.contains(&port) {
+                return Err(PolicyViolation::Port(port));
+            }
+        }
+        let host = host.trim_end_matches('.');
+        let allowed = match &self.allowed_hosts {
+            Some(patterns) => patterns.iter().any(|pattern| host_matches(pattern, host)),
+            None => true,
+        };
+        if !allowed || self.denied_hosts.iter().any(|pattern| host_matches(pattern, host)) {
+            return Err(PolicyViolation::Host(host.to_string()));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_address(&self, addr: IpAddr) -> Result<(), PolicyViolation> {
+        let embedded = embedded_ipv4(addr);
+        let contains = |net: &IpNet| net.contains(&addr) || embedded.is_some_and(|embedded| net.contains(&embedded));
+        if self.allowed_networks.iter().any(contains) {
+            return Ok(());
+        }
+        if self.denied_networks.iter().any(contains) {
+            return Err(PolicyViolation::Address(addr));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the IPv4 address an IPv6 address reaches through IPv4-mapping, the deprecated IPv4-compatible
+/// addresses (`::a.b.c.d`), NAT64 (`64:ff9b::/96`) or 6to4 (`2002::/16`), so it is checked like that IPv4
+/// address.
+fn embedded_ipv4(addr: IpAddr) -> Option<IpAddr> {
+    let IpAddr::V6(addr) = addr else {
+        return None;
+    };
+    if let Some(embedded) = addr.to_ipv4() {
+        return Some(embedded.into());
+    }
+    let [a, b, c, d, e, f, g, h] = addr.segments();
+    let embedded = match [a, b, c, d, e, f] {
+        [0x64, 0xff9b, 0, 0, 0, 0] => [g, h],
+        _ if a == 0x2002 => [b, c],
+        _ => return None,
+    };
+    Some(Ipv4Addr::from((embedded[0] as u32) << 16 | embedded[1] as u32).into())
+}
+
+/// Converts the host name or domain of a pattern to ASCII, like the hosts of requests.
+fn normalize_pattern(pattern: String) -> String {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => format!("*.{}", normalize_host(domain)),
+        None => normalize_host(&pattern),
+    }
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.len() > domain.len() && host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'),
+        None => pattern == host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_public_only() {
+        let policy = OutboundPolicy::public_only();
+        for denied in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "169.254.169.254",
+            "192.168.1.1",
+            "::1",
+            "fe80::1",
+            "fec0::1",
+            "feff::1",
+            "::127.0.0.1",
+            "::a9fe:a9fe",
+            "::10.0.0.1",
+            "fd00:ec2::254",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::127.0.0.1",
+            "2002:a9fe:a9fe::1",
+            "2002:c0a8:101:1::1",
+        ] {
+            let addr: IpAddr = denied.parse().unwrap();
+            assert_eq!(policy.check_address(addr), Err(PolicyViolation::Address(addr)), "{}", denied);
+        }
+        for allowed in [
+            "93.184.216.34",
+            "2606:2800:220:1:248:1893:25c8:1946",
+            "64:ff9b::5db8:d822",
+            "2002:5db8:d822::1",
+            "::93.184.216.34",
+        ] {
+            assert!(policy.check_address(allowed.parse().unwrap()).is_ok(), "{}", allowed);
+        }
+        let policy = policy.with_allowed_networks(["10.0.0.0/24".parse().unwrap()]);
+        assert!(policy.check_address("10.0.0.5".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_check_origin() {
+        let policy = OutboundPolicy::new()
+            .with_allowed_schemes([Scheme::HTTPS])
+            .with_allowed_ports([443])
+            .with_allowed_hosts(["*.example.com", "example.org"])
+            .with_denied_hosts(["internal.example.com"]);
+        assert!(policy.check_origin(&Scheme::HTTPS, "api.example.com", 443).is_ok());
+        assert!(policy.check_origin(&Scheme::HTTPS, "example.org.", 443).is_ok());
+        assert_eq!(
+            policy.check_origin(&Scheme::HTTP, "example.org", 443),
+            Err(PolicyViolation::Scheme(Scheme::HTTP))
+        );
+        assert_eq!(policy.check_origin(&Scheme::HTTPS, "example.org", 8443), Err(PolicyViolation::Port(8443)));
+        assert!(policy.check_origin(&Scheme::HTTPS, "example.com", 443).is_err());
+        assert!(policy.check_origin(&Scheme::HTTPS, "badexample.com", 443).is_err());
+        assert!(policy.check_origin(&Scheme::HTTPS, "internal.example.com", 443).is_err());
+    }
+
+    #[test]
+    fn test_idna_host_patterns() {
+        let policy = OutboundPolicy::new()
+            .with_allowed_hosts(["*.Bücher.example", "Straße.example"])
+            .with_denied_hosts(["INTERN.bücher.example"]);
+        assert!(policy.check_origin(&Scheme::HTTPS, "shop.xn--bcher-kva.example", 443).is_ok());
+        assert!(policy.check_origin(&Scheme::HTTPS, "xn--strae-oqa.example", 443).is_ok());
+        assert!(policy.check_origin(&Scheme::HTTPS, "intern.xn--bcher-kva.example", 443).is_err());
+        assert!(policy.check_origin(&Scheme::HTTPS, "xn--bcher-kva.example", 443).is_err());
+    }
+}
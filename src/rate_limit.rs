@@ -0,0 +1,367 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use http::{HeaderMap, StatusCode};
+
+use crate::http::normalize_host;
+use crate::retry::parse_retry_after;
+
+/// Delays requests to stay within the quotas of servers, see [`Connector::with_rate_limiter`](crate::Connector::with_rate_limiter).
+///
+/// Every host or route has its own token bucket. A route limit applies to requests to its host whose path
+/// starts with its prefix, taking precedence over host limits, which take precedence over the default limit.
+/// Requests without any configured limit are only delayed when the server asked for it.
+///
+/// Unless disabled, the remaining quota and reset reported by the `RateLimit` header or the `RateLimit-Remaining`
+/// and `RateLimit-Reset` headers (or their `X-RateLimit-*` variants) of responses are used to wait for the reset
+/// once the quota is used up, and `429 Too Many Requests` responses pause requests until their `Retry-After` (by default
+/// for one second). Requests waiting for a reset are spaced out like the configured limit, or else like the limit
+/// reported by the server spread over the time until the reset. Clones share their buckets.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    default_limit: Option<RateLimit>,
+    host_limits: HashMap<String, RateLimit>,
+    route_limits: Vec<(String, String, RateLimit)>,
+    ignore_headers: bool,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+/// Allows `requests` per `period` on average, with bursts of up to `burst` requests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    requests: u32,
+    period: Duration,
+    burst: u32,
+}
+
+struct Bucket {
+    limit: Option<RateLimit>,
+    tokens: f64,
+    refilled_at: Instant,
+    quota: Option<Quota>,
+    blocked_until: Option<Instant>,
+}
+
+/// Quota reported by the server, remaining until the reset.
+struct Quota {
+    remaining: u64,
+    /// Once the quota is used up, when the next request may be sent.
+    reset: Instant,
+    /// Time between requests waiting for the reset.
+    spacing: Duration,
+}
+
+impl RateLimit {
+    pub fn new(requests: u32, period: Duration) -> Self {
+        Self {
+            requests: requests.max(1),
+            period,
+            burst: requests.max(1),
+        }
+    }
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+    fn per_second(&self) -> f64 {
+        self.requests as f64 / self.period.as_secs_f64()
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Limits hosts without a more specific limit, each host having its own bucket.
+    pub fn with_default_limit(mut self, limit: RateLimit) -> Self {
+        self.default_limit = Some(limit);
+        self
+    }
+    pub fn with_host_limit(mut self, host: impl Into<String>, limit: RateLimit) -> Self {
+        self.host_limits.insert(normalize_host(&host.into()), limit);
+        self
+    }
+    /// Limits requests to `host` whose path starts with `path_prefix`.
+    pub fn with_route_limit(mut self, host: impl Into<String>, path_prefix: impl Into<String>, limit: RateLimit) -> Self {
+        self.route_limits.push((normalize_host(&host.into()), path_prefix.into(), limit));
+        self
+    }
+    /// Ignores rate limit headers and `429` responses.
+    pub fn with_headers_ignored(mut self, ignored: bool) -> Self {
+        self.ignore_headers = ignored;
+        self
+    }
+
+    /// Takes a token from the bucket of the request, returning the key of the bucket and how long to wait before sending.
+    pub(crate) fn reserve(&self, host: &str, path: &str) -> (String, Option<Duration>) {
+        let route = self
+            .route_limits
+            .iter()
+            .filter(|(route_host, prefix, _)| route_host == host && path.starts_with(prefix.as_str()))
+            .max_by_key(|(_, prefix, _)| prefix.len());
+        let (key, limit) = match route {
+            Some((_, prefix, limit)) => (format!("{}{}", host, prefix), Some(*limit)),
+            None => (host.to_string(), self.host_limits.get(host).or(self.default_limit.as_ref()).copied()),
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        // requests without a limit only need a bucket once the server reported one
+        let bucket = match (buckets.contains_key(&key), limit) {
+            (true, _) => buckets.get_mut(&key).unwrap(),
+            (false, Some(limit)) => insert_bucket(&mut buckets, key.clone(), Some(limit), now),
+            (false, None) => return (key, None),
+        };
+        let mut ready_at = now;
+        if let Some(limit) = bucket.limit {
+            bucket.refill(now);
+            bucket.tokens -= 1.0;
+            if bucket.tokens < 0.0 {
+                ready_at += Duration::from_secs_f64(-bucket.tokens / limit.per_second());
+            }
+        }
+        if let Some(blocked_until) = bucket.blocked_until {
+            ready_at = ready_at.max(blocked_until);
+        }
+        let spacing = bucket.limit.map(|limit| Duration::from_secs_f64(1.0 / limit.per_second()));
+        match &mut bucket.quota {
+            Some(quota) if quota.reset <= ready_at => bucket.quota = None,
+            Some(quota) if quota.remaining == 0 => {
+                ready_at = quota.reset;
+                quota.reset += spacing.unwrap_or(quota.spacing);
+            }
+            Some(quota) => quota.remaining -= 1,
+            None => {}
+        }
+        let delay = ready_at.checked_duration_since(now).filter(|delay| !delay.is_zero());
+        (key, delay)
+    }
+
+    /// Adapts the bucket `key` to the rate limit headers and status of a response.
+    pub(crate) fn update(&self, key: &str, status: StatusCode, headers: &HeaderMap) {
+        if self.ignore_headers {
+            return;
+        }
+        let now = Instant::now();
+        let header = |names: [&str; 2]| names.iter().find_map(|name| headers.get(*name)).and_then(|value| value.to_str().ok());
+        let mut quota = headers
+            .get("ratelimit")
+            .and_then(|value| value.to_str().ok())
+            .map(parse_structured)
+            .unwrap_or_default();
+        if let Some(limit) = header(["ratelimit-limit", "x-ratelimit-limit"]).and_then(parse_leading_integer) {
+            quota.0 = Some(limit);
+        }
+        if let Some(remaining) = header(["ratelimit-remaining", "x-ratelimit-remaining"]).and_then(parse_leading_integer) {
+            quota.1 = Some(remaining);
+        }
+        if let Some(reset) = header(["ratelimit-reset", "x-ratelimit-reset"]).and_then(parse_leading_integer) {
+            quota.2 = Some(reset);
+        }
+        let retry_after = headers
+            .get(http::header::RETRY_AFTER)
+            .and_then(|value| parse_retry_after(value.to_str().ok()?.trim(), SystemTime::now()));
+        let (limit, remaining, reset) = (quota.0, quota.1, quota.2.map(reset_delay));
+        let limited = status == StatusCode::TOO_MANY_REQUESTS;
+        if !limited && (remaining.is_none() || reset.is_none()) {
+            return;
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = match buckets.contains_key(key) {
+            true => buckets.get_mut(key).unwrap(),
+            false => insert_bucket(&mut buckets, key.to_string(), self.limit(key), now),
+        };
+        if let (Some(remaining), Some(reset)) = (remaining, reset) {
+            let spacing = match limit {
+                Some(limit) => reset / limit.clamp(1, u32::MAX as u64) as u32,
+                None => Duration::from_secs(1),
+            };
+            bucket.quota = Some(Quota {
+                remaining,
+                reset: now + reset,
+                spacing,
+            });
+        }
+        let reset = reset.map(|reset| now + reset);
+        if limited {
+            let blocked_until = match (retry_after, reset) {
+                (Some(retry_after), _) => now + retry_after,
+                (None, Some(reset)) => reset,
+                (None, None) => now + Duration::from_secs(1),
+            };
+            log::debug!("rate limited by {}, pausing requests for {:?}", key, blocked_until - now);
+            bucket.blocked_until = Some(blocked_until);
+        }
+    }
+
+    /// Returns the configured limit of the bucket `key`.
+    fn limit(&self, key: &str) -> Option<RateLimit> {
+        let route = self
+            .route_limits
+            .iter()
+            .rfind(|(host, prefix, _)| key.strip_prefix(host.as_str()) == Some(prefix.as_str()));
+        match route {
+            Some((_, _, limit)) => Some(*limit),
+            None => self.host_limits.get(key).or(self.default_limit.as_ref()).copied(),
+        }
+    }
+}
+
+/// Inserts a full bucket, first removing the buckets that are back in their initial state.
+fn insert_bucket(buckets: &mut HashMap<String, Bucket>, key: String, limit: Option<RateLimit>, now: Instant) -> &mut Bucket {
+    buckets.retain(|_, bucket| !bucket.is_expired(now));
+    buckets.entry(key).or_insert(Bucket {
+        limit,
+        tokens: limit.map_or(0.0, |limit| limit.burst as f64),
+        refilled_at: now,
+        quota: None,
+        blocked_until: None,
+    })
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        if let Some(limit) = self.limit {
+            let refill = now.duration_since(self.refilled_at).as_secs_f64() * limit.per_second();
+            self.tokens = (self.tokens + refill).min(limit.burst as f64);
+            self.refilled_at = now;
+        }
+    }
+
+    /// Whether the bucket is full and not waiting for a reset, so removing it changes nothing.
+    fn is_expired(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        let full = self.limit.is_none_or(|limit| self.tokens >= limit.burst as f64);
+        full && self.quota.as_ref().is_none_or(|quota| quota.reset <= now) && self.blocked_until.is_none_or(|until| until <= now)
+    }
+}
+
+/// Parses the `RateLimit` header, e.g. `limit=100, remaining=50, reset=5`, into the limit, remaining quota and reset.
+fn parse_structured(value: &str) -> (Option<u64>, Option<u64>, Option<u64>) {
+    let mut quota = (None, None, None);
+    for parameter in value.split([',', ';']) {
+        match parameter.trim().split_once('=') {
+            Some(("limit" | "l", limit)) => quota.0 = limit.trim().parse().ok(),
+            Some(("remaining" | "r", remaining)) => quota.1 = remaining.trim().parse().ok(),
+            Some(("reset" | "t", reset)) => quota.2 = reset.trim().parse().ok(),
+            _ => {}
+        }
+    }
+    quota
+}
+
+/// Parses values like `100` or `100, 100;w=60`.
+fn parse_leading_integer(value: &str) -> Option<u64> {
+    value.split([',', ';']).next()?.trim().parse().ok()
+}
+
+/// Reset values are seconds until the reset, but some servers send a Unix timestamp instead.
+fn reset_delay(reset: u64) -> Duration {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    match reset > 1_000_000_000 {
+        true => Duration::from_secs(reset).saturating_sub(now),
+        false => Duration::from_secs(reset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::{HeaderMap, HeaderValue, StatusCode};
+
+    use super::{parse_structured, RateLimit, RateLimiter};
+
+    #[test]
+    fn token_buckets() {
+        let limiter = RateLimiter::new()
+            .with_host_limit("example.com", RateLimit::new(2, Duration::from_secs(1)))
+            .with_route_limit("example.com", "/slow", RateLimit::new(1, Duration::from_secs(10)));
+        assert_eq!(limiter.reserve("example.com", "/"), ("example.com".to_string(), None));
+        assert_eq!(limiter.reserve("example.com", "/").1, None);
+        let delay = limiter.reserve("example.com", "/").1.unwrap();
+        assert!(delay > Duration::from_millis(450) && delay <= Duration::from_millis(500));
+        assert_eq!(limiter.reserve("example.com", "/slow/1"), ("example.com/slow".to_string(), None));
+        assert!(limiter.reserve("example.com", "/slow/2").1.unwrap() > Duration::from_secs(9));
+        assert_eq!(limiter.reserve("other.com", "/").1, None);
+    }
+
+    #[test]
+    fn idna_host_keys() {
+        let limiter = RateLimiter::new()
+            .with_host_limit("Bücher.example", RateLimit::new(1, Duration::from_secs(10)))
+            .with_route_limit("BÜCHER.example", "/slow", RateLimit::new(1, Duration::from_secs(10)));
+        assert_eq!(limiter.reserve("xn--bcher-kva.example", "/"), ("xn--bcher-kva.example".to_string(), None));
+        assert!(limiter.reserve("xn--bcher-kva.example", "/").1.is_some());
+        assert_eq!(
+            limiter.reserve("xn--bcher-kva.example", "/slow"),
+            ("xn--bcher-kva.example/slow".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn adapt_to_headers() {
+        let limiter = RateLimiter::new();
+        let (key, _) = limiter.reserve("example.com", "/");
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("1"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("30"));
+        limiter.update(&key, StatusCode::OK, &headers);
+        assert_eq!(limiter.reserve("example.com", "/").1, None);
+        let first = limiter.reserve("example.com", "/").1.unwrap();
+        assert!(first > Duration::from_secs(29) && first <= Duration::from_secs(30));
+        let second = limiter.reserve("example.com", "/").1.unwrap();
+        assert!(second > first + Duration::from_millis(900));
+        assert_eq!(parse_structured("limit=100, remaining=50, reset=5"), (Some(100), Some(50), Some(5)));
+    }
+
+    #[test]
+    fn space_out_after_reset() {
+        let limiter = RateLimiter::new();
+        let mut headers = HeaderMap::new();
+        headers.insert("ratelimit", HeaderValue::from_static("limit=10, remaining=0, reset=10"));
+        limiter.update("example.com", StatusCode::OK, &headers);
+        let delays: Vec<_> = (0..3).map(|_| limiter.reserve("example.com", "/").1.unwrap()).collect();
+        assert!(delays[0] > Duration::from_millis(9900));
+        assert!(delays[1] - delays[0] > Duration::from_millis(990) && delays[2] - delays[1] > Duration::from_millis(990));
+
+        let limiter = RateLimiter::new().with_host_limit("example.com", RateLimit::new(100, Duration::from_secs(1)));
+        limiter.reserve("example.com", "/");
+        limiter.update("example.com", StatusCode::OK, &headers);
+        let delays: Vec<_> = (0..2).map(|_| limiter.reserve("example.com", "/").1.unwrap()).collect();
+        let spacing = delays[1] - delays[0];
+        assert!(spacing > Duration::from_millis(9) && spacing < Duration::from_millis(11));
+    }
+
+    #[test]
+    fn buckets_only_for_limits() {
+        let limiter = RateLimiter::new()
+            .with_host_limit("example.com", RateLimit::new(1000, Duration::from_secs(1)))
+            .with_route_limit("example.com", "/slow", RateLimit::new(1, Duration::from_secs(10)));
+        for i in 0..100 {
+            let (key, delay) = limiter.reserve(&format!("host{}.example.org", i), &format!("/{}", i));
+            assert_eq!(delay, None);
+            limiter.update(&key, StatusCode::OK, &HeaderMap::new());
+        }
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+
+        limiter.reserve("example.com", "/");
+        limiter.reserve("example.com", "/slow");
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("1"));
+        limiter.update("other.com", StatusCode::TOO_MANY_REQUESTS, &headers);
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 3);
+        assert!(limiter.reserve("other.com", "/").1.is_some());
+
+        // the host bucket refills within a few milliseconds and is removed once another bucket is created
+        std::thread::sleep(Duration::from_millis(10));
+        limiter.reserve("example.com", "/slow/2");
+        limiter.update("new.com", StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new());
+        let buckets = limiter.buckets.lock().unwrap();
+        let mut keys: Vec<_> = buckets.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["example.com/slow", "new.com", "other.com"]);
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use http::{header::RETRY_AFTER, HeaderMap, Method, StatusCode};
+
+use crate::{date, HttpError, TimeoutPhase, TransportError};
+
+type Predicate = Arc<dyn Fn(&Method, &RetryCause<'_>) -> bool + Send + Sync>;
+
+/// Retries requests failing with transient errors, see [`Connector::with_retry_policy`](crate::Connector::with_retry_policy).
+///
+/// By default a request is attempted up to 3 times. Retries wait for an exponentially growing backoff with full
+/// jitter, starting at 100ms and capped at 10s, unless the response contains a `Retry-After` header, which is
+/// honoured up to 60s. Longer `Retry-After` values are not waited for and the response is returned instead.
+/// Only idempotent methods are retried and timeouts apply to each attempt separately.
+///
+/// The request body is rewound for every retry. If that is impossible, see [`RequestBody`](crate::RequestBody),
+/// the result of the last attempt is returned without retrying.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    max_retry_after: Duration,
+    non_idempotent: bool,
+    predicate: Option<Predicate>,
+}
+
+/// Outcome of an attempt that may be retried.
+#[derive(Debug)]
+pub enum RetryCause<'a> {
+    Error(&'a HttpError),
+    Response { status: StatusCode, headers: &'a HeaderMap },
+}
+
+/// Number of attempts made for a request, available in the response extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attempts(pub u32);
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+            max_retry_after: Duration::from_secs(60),
+            non_idempotent: false,
+            predicate: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Maximum number of attempts including the first one.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+    /// Backoff before the first retry, doubled for every further retry up to `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+    /// Waits for a random duration between zero and the backoff instead of the backoff itself.
+    pub fn with_jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+    pub fn with_max_retry_after(mut self, max: Duration) -> Self {
+        self.max_retry_after = max;
+        self
+    }
+    /// Also retries methods that are not idempotent, like `POST` and `PATCH`.
+    pub fn with_non_idempotent_methods(mut self, enabled: bool) -> Self {
+        self.non_idempotent = enabled;
+        self
+    }
+    /// Decides which outcomes are retried instead of [`RetryCause::is_transient`]. Attempt limits and the
+    /// method restriction still apply.
+    pub fn with_predicate(mut self, predicate: impl Fn(&Method, &RetryCause<'_>) -> bool + Send + Sync + 'static) -> Self {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+
+    pub(crate) fn allows_method(&self, method: &Method) -> bool {
+        self.non_idempotent
+            || matches!(
+                *method,
+                Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+            )
+    }
+
+    /// Returns how long to wait before the next attempt, or `None` if `result` is final.
+    pub(crate) fn retry_delay<B>(&self, method: &Method, attempts: u32, result: &Result<http::Response<B>, HttpError>) -> Option<Duration> {
+        if attempts >= self.max_attempts || !self.allows_method(method) {
+            return None;
+        }
+        let cause = match result {
+            Ok(response) => RetryCause::Response {
+                status: response.status(),
+                headers: response.headers(),
+            },
+            Err(err) => RetryCause::Error(err),
+        };
+        let retry = match &self.predicate {
+            Some(predicate) => predicate(method, &cause),
+            None => cause.is_transient(),
+        };
+        if !retry {
+            return None;
+        }
+        match cause.retry_after() {
+            Some(retry_after) if retry_after > self.max_retry_after => None,
+            Some(retry_after) => Some(retry_after),
+            None => Some(self.backoff(attempts)),
+        }
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let backoff = self.initial_backoff.saturating_mul(1 << (attempts - 1).min(31)).min(self.max_backoff);
+        match self.jitter {
+            true => backoff.mul_f64(fastrand::f64()),
+            false => backoff,
+        }
+    }
+}
+
+impl RetryCause<'_> {
+    /// Whether the outcome is likely to differ on another attempt: connection failures, connect and TLS handshake
+    /// timeouts, connections closed unexpectedly and the statuses 429, 502, 503 and 504.
+    pub fn is_transient(&self) -> bool {
+        let transient_io = |err: &io::Error| {
+            matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof
+            )
+        };
+        match self {
+            RetryCause::Error(HttpError::ConnectError(TransportError::TcpConnect(_))) => true,
+            RetryCause::Error(HttpError::ConnectError(TransportError::TlsConnect(err))) => transient_io(err),
+            RetryCause::Error(HttpError::Timeout(TimeoutPhase::Connect | TimeoutPhase::TlsHandshake)) => true,
+            RetryCause::Error(HttpError::IoError(err)) => transient_io(err),
+            RetryCause::Error(_) => false,
+            RetryCause::Response { status, .. } => matches!(
+                *status,
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+            ),
+        }
+    }
+    /// Delay requested by the `Retry-After` header, in seconds or as HTTP date.
+    pub fn retry_after(&self) -> Option<Duration> {
+        let headers = match self {
+            RetryCause::Response { headers, .. } => headers,
+            RetryCause::Error(_) => return None,
+        };
+        parse_retry_after(headers.get(RETRY_AFTER)?.to_str().ok()?.trim(), SystemTime::now())
+    }
+}
+
+pub(crate) fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = date::parse_http_date(value)?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::{Duration, SystemTime},
+    };
+
+    use futures::executor::block_on;
+    use http::{Method, Request, Response, StatusCode};
+
+    use super::{parse_retry_after, Attempts, RetryPolicy};
+    use crate::test_util::{read_head, serve};
+    use crate::{Connector, RequestBody, RequestWithoutBodyExt};
+
+    #[test]
+    fn parse_retry_after_values() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:50:07 GMT", now), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:00:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("-1", now), None);
+    }
+
+    #[test]
+    fn retry_delays() {
+        let policy = RetryPolicy::new().with_jitter(false);
+        let response = |status| Ok(Response::builder().status(status).header("retry-after", "2").body(()).unwrap());
+        assert_eq!(
+            policy.retry_delay(&Method::GET, 1, &response(StatusCode::SERVICE_UNAVAILABLE)),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(policy.retry_delay(&Method::GET, 3, &response(StatusCode::SERVICE_UNAVAILABLE)), None);
+        assert_eq!(policy.retry_delay(&Method::POST, 1, &response(StatusCode::SERVICE_UNAVAILABLE)), None);
+        assert_eq!(policy.retry_delay(&Method::GET, 1, &response(StatusCode::INTERNAL_SERVER_ERROR)), None);
+        let failure = Err(crate::HttpError::Timeout(crate::TimeoutPhase::Connect));
+        assert_eq!(policy.retry_delay::<()>(&Method::GET, 2, &failure), Some(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn retry_unavailable() {
+        let requests = Arc::new(AtomicU32::new(0));
+        let counter = requests.clone();
+        let addr = serve(move |mut stream| {
+            read_head(&mut stream);
+            let response: &[u8] = match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                _ => b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+            };
+            stream.write_all(response).unwrap();
+        });
+        let connector = Connector::default().with_retry_policy(RetryPolicy::new().with_backoff(Duration::ZERO, Duration::ZERO));
+        let request = Request::get(format!("http://{}/", addr)).body(()).unwrap();
+        let mut response = block_on(request.send_with_connector((), connector)).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.extensions().get::<Attempts>(), Some(&Attempts(2)));
+        assert_eq!(block_on(response.body_mut().string(None)).unwrap(), "ok");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn body_not_rewindable() {
+        let requests = Arc::new(AtomicU32::new(0));
+        let counter = requests.clone();
+        let addr = serve(move |mut stream| {
+            read_head(&mut stream);
+            stream.read_exact(&mut [0; 4]).unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            stream
+                .write_all(b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .unwrap();
+        });
+        let connector = Connector::default().with_retry_policy(RetryPolicy::new().with_backoff(Duration::ZERO, Duration::ZERO));
+        let request = Request::put(format!("http://{}/", addr)).body(()).unwrap();
+        let body = RequestBody::streaming(&b"body"[..], 4);
+        let response = block_on(request.send_with_connector(body, connector)).unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.extensions().get::<Attempts>(), Some(&Attempts(1)));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::RangeInclusive,
+    time::Duration,
+};
+
+use async_io::Async;
+use async_net::TcpStream;
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+
+/// Socket options applied to every socket opened by a [`Connector`](crate::Connector) before connecting.
+///
+/// Options left at their default keep the operating system defaults. Options documented as
+/// Linux-only fail the connection with [`io::ErrorKind::Unsupported`] on other platforms.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SocketOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<Keepalive>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    user_timeout: Option<Duration>,
+    fast_open: bool,
+    multipath: bool,
+    local_ipv4: Option<Ipv4Addr>,
+    local_ipv6: Option<Ipv6Addr>,
+    local_port_range: Option<RangeInclusive<u16>>,
+    bind_device: Option<String>,
+    mark: Option<u32>,
+}
+
+/// TCP keepalive parameters. Unset values keep the operating system defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Keepalive {
+    pub time: Option<Duration>,
+    pub interval: Option<Duration>,
+    pub retries: Option<u32>,
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets `TCP_NODELAY`, disabling Nagle's algorithm if `true`.
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+    /// Enables `SO_KEEPALIVE` with the given parameters.
+    pub fn with_keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+    /// Sets `SO_SNDBUF`.
+    pub fn with_send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+    /// Sets `SO_RCVBUF`.
+    pub fn with_recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+    /// Sets `TCP_USER_TIMEOUT` (Linux only).
+    pub fn with_user_timeout(mut self, timeout: Duration) -> Self {
+        self.user_timeout = Some(timeout);
+        self
+    }
+    /// Enables TCP Fast Open using `TCP_FASTOPEN_CONNECT` (Linux only).
+    pub fn with_fast_open(mut self, fast_open: bool) -> Self {
+        self.fast_open = fast_open;
+        self
+    }
+    /// Opens Multipath TCP sockets instead of plain TCP sockets (Linux only).
+    pub fn with_multipath(mut self, multipath: bool) -> Self {
+        self.multipath = multipath;
+        self
+    }
+
+    /// Binds outgoing sockets to a local address. Can be set once per address family. If a local
+    /// address is configured only for one family, peers of the other family are not connected to.
+    pub fn with_local_address(mut self, address: IpAddr) -> Self {
+        match address {
+            IpAddr::V4(address) => self.local_ipv4 = Some(address),
+            IpAddr::V6(address) => self.local_ipv6 = Some(address),
+        }
+        self
+    }
+    /// Binds outgoing sockets to a local port from the given range, starting at a random port.
+    pub fn with_local_port_range(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.local_port_range = Some(ports);
+        self
+    }
+    /// Sets `SO_BINDTODEVICE` to send traffic via the given interface (Linux only).
+    pub fn with_bind_device(mut self, interface: impl Into<String>) -> Self {
+        self.bind_device = Some(interface.into());
+        self
+    }
+    /// Sets `SO_MARK` for policy routing (Linux only).
+    pub fn with_mark(mut self, mark: u32) -> Self {
+        self.mark = Some(mark);
+        self
+    }
+
+    pub(crate) async fn connect_addr(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let local_ip = match addr {
+            SocketAddr::V4(_) => self.local_ipv4.map(IpAddr::V4),
+            SocketAddr::V6(_) => self.local_ipv6.map(IpAddr::V6),
+        };
+        if local_ip.is_none() && (self.local_ipv4.is_some() || self.local_ipv6.is_some()) {
+            let message = format!("no local address configured for address family of {}", addr);
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, message));
+        }
+        let ports = match &self.local_port_range {
+            None => {
+                let socket = self.socket(addr)?;
+                if let Some(local_ip) = local_ip {
+                    socket.bind(&SocketAddr::new(local_ip, 0).into())?;
+                }
+                return Self::connect_socket(socket, addr).await;
+            }
+            Some(ports) => ports,
+        };
+        let local_ip = local_ip.unwrap_or(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        });
+        let count = (*ports.end() as u32 + 1).saturating_sub(*ports.start() as u32);
+        let offset = fastrand::u32(0..count.max(1));
+        for i in 0..count {
+            let port = (*ports.start() as u32 + (offset + i) % count) as u16;
+            let socket = self.socket(addr)?;
+            match socket.bind(&SocketAddr::new(local_ip, port).into()) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(err) => return Err(err),
+            }
+            match Self::connect_socket(socket, addr).await {
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse || err.kind() == io::ErrorKind::AddrNotAvailable => continue,
+                result => return result,
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::AddrInUse, "no free local port in configured range"))
+    }
+
+    async fn connect_socket(socket: Socket, addr: SocketAddr) -> io::Result<TcpStream> {
+        socket.set_nonblocking(true)?;
+        match socket.connect(&addr.into()) {
+            Ok(()) => {}
+            #[cfg(unix)]
+            Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err),
+        }
+        let stream = Async::new(std::net::TcpStream::from(socket))?;
+        stream.writable().await?;
+        if let Some(err) = stream.get_ref().take_error()? {
+            return Err(err);
+        }
+        Ok(stream.into())
+    }
+
+    fn socket(&self, addr: SocketAddr) -> io::Result<Socket> {
+        let protocol = match self.multipath {
+            false => Protocol::TCP,
+            #[cfg(target_os = "linux")]
+            true => Protocol::MPTCP,
+            #[cfg(not(target_os = "linux"))]
+            true => return Err(unsupported("multipath tcp is only supported on linux")),
+        };
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(protocol))?;
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        if let Some(keepalive) = &self.keepalive {
+            socket.set_tcp_keepalive(&keepalive.to_tcp_keepalive())?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(timeout) = self.user_timeout {
+            socket.set_tcp_user_timeout(Some(timeout))?;
+        }
+        #[cfg(target_os = "linux")]
+        if self.fast_open {
+            set_fast_open_connect(&socket)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = &self.bind_device {
+            socket.bind_device(Some(interface.as_bytes()))?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(mark) = self.mark {
+            socket.set_mark(mark)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.user_timeout.is_some() || self.fast_open || self.bind_device.is_some() || self.mark.is_some() {
+            return Err(unsupported(
+                "tcp user timeout, tcp fast open, device binding and marks are only supported on linux",
+            ));
+        }
+        Ok(socket)
+    }
+}
+
+impl Keepalive {
+    fn to_tcp_keepalive(&self) -> TcpKeepalive {
+        let mut keepalive = TcpKeepalive::new();
+        if let Some(time) = self.time {
+            keepalive = keepalive.with_time(time);
+        }
+        if let Some(interval) = self.interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+        if let Some(retries) = self.retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+        keepalive
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_fast_open_connect(socket: &Socket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let enable: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN_CONNECT,
+            &enable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::{Ipv4Addr, Ipv6Addr, TcpListener},
+        time::Duration,
+    };
+
+    use futures::executor::block_on;
+    use socket2::SockRef;
+
+    use super::{Keepalive, SocketOptions};
+
+    #[test]
+    fn options_applied() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let keepalive = Keepalive {
+            time: Some(Duration::from_secs(30)),
+            interval: Some(Duration::from_secs(5)),
+            retries: Some(3),
+        };
+        let options = SocketOptions::new()
+            .with_nodelay(true)
+            .with_keepalive(keepalive)
+            .with_send_buffer_size(64 * 1024)
+            .with_recv_buffer_size(64 * 1024);
+        #[cfg(target_os = "linux")]
+        let options = options.with_user_timeout(Duration::from_secs(10));
+        let stream = block_on(options.connect_addr(listener.local_addr().unwrap())).unwrap();
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        // the kernel may round buffer sizes up, e.g. linux doubles them
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+            assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(5));
+            assert_eq!(socket.keepalive_retries().unwrap(), 3);
+            assert_eq!(socket.tcp_user_timeout().unwrap(), Some(Duration::from_secs(10)));
+        }
+    }
+
+    #[test]
+    fn local_address_and_port_range() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let occupied = TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy = occupied.local_addr().unwrap().port();
+        let options = SocketOptions::new()
+            .with_local_address(Ipv4Addr::LOCALHOST.into())
+            .with_local_port_range(busy..=busy.saturating_add(1));
+        let stream = block_on(options.connect_addr(addr)).unwrap();
+        let local = stream.local_addr().unwrap();
+        assert_eq!(local.ip(), Ipv4Addr::LOCALHOST);
+        assert_eq!(local.port(), busy.saturating_add(1));
+        let err = block_on(options.with_local_port_range(busy..=busy).connect_addr(addr)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        let ipv6_only = SocketOptions::new().with_local_address(Ipv6Addr::LOCALHOST.into());
+        let err = block_on(ipv6_only.connect_addr(addr)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+    }
+}
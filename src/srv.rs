@@ -0,0 +1,404 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_net::{TcpStream, UdpSocket};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use http::{uri::PathAndQuery, Uri};
+
+use crate::{
+    dns::{self, Response},
+    timeout::with_timeout,
+    HttpError, TimeoutPhase, TransportError,
+};
+
+type Cache = Arc<Mutex<HashMap<String, (Vec<SrvTarget>, Instant)>>>;
+
+/// Resolves SRV records for `http+srv` and `https+srv` URIs, see [`Connector::with_srv_resolver`](crate::Connector::with_srv_resolver).
+///
+/// A request to `http+srv://_api._tcp.service.local/path` is sent to one of the targets of the SRV records of
+/// `_api._tcp.service.local` using the port of the record, trying the other targets if connecting fails or their
+/// circuit is open. Targets are tried by priority and chosen randomly by weight within a priority (RFC 2782).
+/// Records are cached until their TTL expires, or until connecting to all of their targets failed. Lookups use
+/// the nameservers of `/etc/resolv.conf` unless configured otherwise, over UDP with a fallback to TCP for
+/// truncated responses. Clones share their cache.
+#[derive(Clone)]
+pub struct SrvResolver {
+    nameservers: Vec<SocketAddr>,
+    timeout: Duration,
+    cache: Cache,
+}
+
+/// Target of an SRV record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub host: String,
+}
+
+impl Default for SrvResolver {
+    fn default() -> Self {
+        Self {
+            nameservers: Vec::new(),
+            timeout: Duration::from_secs(2),
+            cache: Arc::default(),
+        }
+    }
+}
+
+impl SrvResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Queries `nameserver` instead of the system nameservers, trying nameservers in the order they were added.
+    pub fn with_nameserver(mut self, nameserver: SocketAddr) -> Self {
+        self.nameservers.push(nameserver);
+        self
+    }
+    /// How long to wait for the response of a nameserver before trying the next one.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the targets of the SRV records of `name`, e.g. `_api._tcp.service.local`, in the order to try them.
+    pub async fn resolve(&self, name: &str) -> Result<Vec<SrvTarget>, TransportError> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let cached = self.cache.lock().unwrap().get(&name).cloned();
+        let targets = match cached {
+            Some((targets, expires)) if expires > Instant::now() => targets,
+            _ => {
+                let (targets, ttl) = self
+                    .lookup(&name)
+                    .await
+                    .map_err(|err| TransportError::SrvLookup(name.clone(), Arc::new(err)))?;
+                log::debug!("resolved {} to {} targets valid for {}s", name, targets.len(), ttl);
+                let now = Instant::now();
+                let mut cache = self.cache.lock().unwrap();
+                cache.retain(|_, (_, expires)| *expires > now);
+                cache.insert(name, (targets.clone(), now + Duration::from_secs(ttl.into())));
+                targets
+            }
+        };
+        Ok(order(targets))
+    }
+
+    /// Forgets the cached records of `name`, so the next request resolves them again.
+    pub(crate) fn invalidate(&self, name: &str) {
+        self.cache.lock().unwrap().remove(name);
+    }
+
+    async fn lookup(&self, name: &str) -> io::Result<(Vec<SrvTarget>, u32)> {
+        let nameservers = match self.nameservers.is_empty() {
+            true => system_nameservers(),
+            false => self.nameservers.clone(),
+        };
+        let mut last_err = None;
+        for nameserver in nameservers {
+            let query = with_timeout(Some(self.timeout), TimeoutPhase::Connect, query(nameserver, name)).await;
+            let records = match query {
+                Ok(Ok(records)) => records,
+                Ok(Err(err)) if err.kind() == io::ErrorKind::NotFound => return Err(err),
+                Ok(Err(err)) => {
+                    last_err = Some(err);
+                    continue;
+                }
+                Err(_) => {
+                    last_err = Some(io::Error::new(io::ErrorKind::TimedOut, format!("nameserver {} timed out", nameserver)));
+                    continue;
+                }
+            };
+            // a target of "." means the service is not available at this domain
+            if records.is_empty() || records.iter().all(|record| record.target.is_empty()) {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no srv records"));
+            }
+            let ttl = records.iter().map(|record| record.ttl).min().unwrap_or_default();
+            let targets = records
+                .into_iter()
+                .filter(|record| !record.target.is_empty())
+                .map(|record| SrvTarget {
+                    priority: record.priority,
+                    weight: record.weight,
+                    port: record.port,
+                    host: record.target,
+                })
+                .collect();
+            return Ok((targets, ttl));
+        }
+        Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no nameservers")))
+    }
+}
+
+async fn query(nameserver: SocketAddr, name: &str) -> io::Result<Vec<dns::SrvRecord>> {
+    let id = fastrand::u16(..);
+    let query = dns::srv_query(id, name)?;
+    let local = match nameserver {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::from([0u16; 8]), 0),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(nameserver).await?;
+    socket.send(&query).await?;
+    let mut message = vec![0; 4096];
+    loop {
+        let len = socket.recv(&mut message).await?;
+        // ignore stray datagrams, e.g. late responses to an earlier query
+        if len < 2 || message[..2] != id.to_be_bytes() {
+            continue;
+        }
+        match dns::parse_srv_response(id, &message[..len])? {
+            Response::Records(records) => return Ok(records),
+            Response::Truncated => break,
+        }
+    }
+    let mut stream = TcpStream::connect(nameserver).await?;
+    let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(&query);
+    stream.write_all(&framed).await?;
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).await?;
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message).await?;
+    match dns::parse_srv_response(id, &message)? {
+        Response::Records(records) => Ok(records),
+        Response::Truncated => Err(io::Error::new(io::ErrorKind::InvalidData, "truncated tcp response")),
+    }
+}
+
+fn system_nameservers() -> Vec<SocketAddr> {
+    let conf = std::fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    let nameservers: Vec<SocketAddr> = conf
+        .lines()
+        .filter_map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["nameserver", address, ..] => address.parse::<IpAddr>().ok(),
+            _ => None,
+        })
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect();
+    match nameservers.is_empty() {
+        true => vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53)],
+        false => nameservers,
+    }
+}
+
+/// Orders targets by priority and randomly by weight within a priority, as described in RFC 2782.
+fn order(mut targets: Vec<SrvTarget>) -> Vec<SrvTarget> {
+    // targets with weight 0 come first, so they have a small chance of being chosen
+    targets.sort_by_key(|target| (target.priority, target.weight != 0));
+    let mut ordered = Vec::with_capacity(targets.len());
+    while let Some(priority) = targets.first().map(|target| target.priority) {
+        let end = targets.iter().position(|target| target.priority != priority).unwrap_or(targets.len());
+        let mut group: Vec<SrvTarget> = targets.drain(..end).collect();
+        while !group.is_empty() {
+            let mut pick = fastrand::u32(0..=group.iter().map(|target| target.weight as u32).sum());
+            let index = group
+                .iter()
+                .position(|target| match pick <= target.weight as u32 {
+                    true => true,
+                    false => {
+                        pick -= target.weight as u32;
+                        false
+                    }
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(index));
+        }
+    }
+    ordered
+}
+
+/// Whether `scheme` is `http+srv` or `https+srv`, returning whether it uses TLS.
+pub(crate) fn srv_scheme(scheme: Option<&str>) -> Option<bool> {
+    match scheme?.to_ascii_lowercase().as_str() {
+        "http+srv" => Some(false),
+        "https+srv" => Some(true),
+        _ => None,
+    }
+}
+
+/// Targets of a request to an SRV name which have not been tried yet.
+pub(crate) struct SrvTargets {
+    resolver: SrvResolver,
+    name: String,
+    https: bool,
+    remaining: VecDeque<SrvTarget>,
+}
+
+impl SrvTargets {
+    pub fn new(resolver: SrvResolver, name: String, https: bool, targets: Vec<SrvTarget>) -> Self {
+        Self {
+            resolver,
+            name,
+            https,
+            remaining: targets.into(),
+        }
+    }
+    /// Rewrites `uri` onto the next target.
+    pub fn next(&mut self, uri: &Uri) -> Option<Result<Uri, HttpError>> {
+        let target = self.remaining.pop_front()?;
+        let path_and_query = uri.path_and_query().map_or("/", PathAndQuery::as_str);
+        let rewritten = Uri::builder()
+            .scheme(if self.https { "https" } else { "http" })
+            .authority(format!("{}:{}", target.host, target.port))
+            .path_and_query(path_and_query)
+            .build()
+            .map_err(|_| HttpError::InvalidHost(target.host));
+        Some(rewritten)
+    }
+    /// Makes the next request resolve the name again, after connecting to all targets failed.
+    pub fn invalidate(&self) {
+        self.resolver.invalidate(&self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::{TcpListener, UdpSocket},
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use futures::executor::block_on;
+    use http::{Request, StatusCode};
+
+    use super::{order, SrvResolver, SrvTarget};
+    use crate::test_util::{read_head, respond, serve};
+    use crate::{CircuitBreaker, Connector, RequestWithoutBodyExt};
+
+    /// Answers a single SRV query with a record for each `(priority, port)` on `localhost`, valid for `ttl` seconds.
+    fn stub_nameserver(records: Vec<(u16, u16)>, ttl: u32) -> (std::net::SocketAddr, std::thread::JoinHandle<()>) {
+        let stub = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let nameserver = stub.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut query = [0; 512];
+            let (len, peer) = stub.recv_from(&mut query).unwrap();
+            let mut response = query[..len].to_vec();
+            response[2..4].copy_from_slice(&0x8180u16.to_be_bytes());
+            response[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+            for (priority, port) in &records {
+                response.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1]);
+                response.extend_from_slice(&ttl.to_be_bytes());
+                response.extend_from_slice(&[0, 17]);
+                for value in [*priority, 1, *port] {
+                    response.extend_from_slice(&value.to_be_bytes());
+                }
+                response.extend_from_slice(b"\x09localhost\x00");
+            }
+            stub.send_to(&response, peer).unwrap();
+        });
+        (nameserver, server)
+    }
+
+    fn target(priority: u16, weight: u16, host: &str) -> SrvTarget {
+        SrvTarget {
+            priority,
+            weight,
+            port: 80,
+            host: host.to_string(),
+        }
+    }
+
+    #[test]
+    fn order_targets() {
+        let mut first_b = 0;
+        for _ in 0..1000 {
+            let ordered = order(vec![target(20, 0, "d"), target(10, 1, "a"), target(10, 9, "b"), target(5, 0, "c")]);
+            let hosts: Vec<&str> = ordered.iter().map(|target| target.host.as_str()).collect();
+            assert_eq!((hosts[0], hosts[3]), ("c", "d"));
+            if hosts[1] == "b" {
+                first_b += 1;
+            }
+        }
+        // b is picked first with probability 9/11
+        assert!((760..880).contains(&first_b), "{}", first_b);
+    }
+
+    #[test]
+    fn resolve_with_stub() {
+        let stub = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let nameserver = stub.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut query = [0; 512];
+            let (len, peer) = stub.recv_from(&mut query).unwrap();
+            let mut response = query[..len].to_vec();
+            response[2..4].copy_from_slice(&0x8180u16.to_be_bytes());
+            response[6..8].copy_from_slice(&1u16.to_be_bytes());
+            response.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1, 0, 0, 0, 60, 0, 15, 0, 10, 0, 5, 0x1f, 0x90]);
+            response.extend_from_slice(&[5, b'n', b'o', b'd', b'e', b'1', 0xc0, 22]);
+            stub.send_to(&response, peer).unwrap();
+        });
+        let resolver = SrvResolver::new().with_nameserver(nameserver).with_timeout(Duration::from_secs(5));
+        let expected = SrvTarget {
+            priority: 10,
+            weight: 5,
+            port: 8080,
+            host: "node1.service.local".to_string(),
+        };
+        let targets = block_on(resolver.resolve("_api._tcp.service.local")).unwrap();
+        assert_eq!(targets, [expected]);
+        server.join().unwrap();
+        // answered from the cache, the stub only responds once
+        assert_eq!(block_on(resolver.resolve("_api._tcp.Service.local.")).unwrap(), targets);
+    }
+
+    #[test]
+    fn expired_records_evicted() {
+        let (nameserver, server) = stub_nameserver(vec![(10, 8080)], 0);
+        let resolver = SrvResolver::new().with_nameserver(nameserver).with_timeout(Duration::from_secs(5));
+        block_on(resolver.resolve("_api._tcp.service.local")).unwrap();
+        server.join().unwrap();
+        let (nameserver, server) = stub_nameserver(vec![(10, 8080)], 60);
+        let resolver = SrvResolver {
+            nameservers: vec![nameserver],
+            ..resolver
+        };
+        block_on(resolver.resolve("_other._tcp.service.local")).unwrap();
+        server.join().unwrap();
+        let cache = resolver.cache.lock().unwrap();
+        assert_eq!(cache.keys().collect::<Vec<_>>(), ["_other._tcp.service.local"]);
+    }
+
+    #[test]
+    fn failover_to_next_target() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let unavailable_requests = Arc::new(AtomicU32::new(0));
+        let counter = unavailable_requests.clone();
+        let unavailable = serve(move |mut stream| {
+            read_head(&mut stream);
+            counter.fetch_add(1, Ordering::SeqCst);
+            stream
+                .write_all(b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .unwrap();
+        });
+        let available = serve(|mut stream| respond(&mut stream, "ok"));
+        let (nameserver, server) = stub_nameserver(vec![(1, closed), (2, unavailable.port()), (3, available.port())], 60);
+        let connector = Connector::default()
+            .with_srv_resolver(SrvResolver::new().with_nameserver(nameserver).with_timeout(Duration::from_secs(5)))
+            .with_circuit_breaker(CircuitBreaker::new().with_consecutive_failures(1));
+        let send = || {
+            let request = Request::get("http+srv://_api._tcp.service.local/").body(()).unwrap();
+            block_on(async {
+                let mut response = request.send_with_connector((), connector.clone()).await.unwrap();
+                let body = response.body_mut().string(None).await.unwrap();
+                (response.status(), body)
+            })
+        };
+        // the closed port fails to connect, the next target responds with 503
+        assert_eq!(send().0, StatusCode::SERVICE_UNAVAILABLE);
+        server.join().unwrap();
+        // the circuits of both targets are open now, so the request goes to the last target without connecting to them
+        assert_eq!(send(), (StatusCode::OK, "ok".to_string()));
+        assert_eq!(unavailable_requests.load(Ordering::SeqCst), 1);
+    }
+}
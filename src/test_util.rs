@@ -0,0 +1,175 @@
+//! Local servers and certificates for tests.
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::Arc,
+    thread,
+};
+
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+use futures_rustls::rustls::{crypto::CryptoProvider, ServerConfig, ServerConnection, StreamOwned};
+use rcgen::{
+    date_time_ymd, BasicConstraints, Certificate, CertificateParams, CertificateRevocationListParams, IsCa, KeyIdMethod, KeyPair, KeyUsagePurpose,
+    RevocationReason, RevokedCertParams, SerialNumber,
+};
+use rustls_pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+/// Serves every connection to the returned address with `handler` on its own thread.
+pub fn serve(handler: impl Fn(TcpStream) + Send + Sync + 'static) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { break };
+            let handler = handler.clone();
+            thread::spawn(move || handler(stream));
+        }
+    });
+    addr
+}
+
+/// Serves every connection to the returned address with `config`, passing the TLS stream to `handler`
+/// before the handshake.
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+pub fn serve_tls(config: Arc<ServerConfig>, handler: impl Fn(StreamOwned<ServerConnection, TcpStream>) + Send + Sync + 'static) -> SocketAddr {
+    serve(move |stream| {
+        let connection = ServerConnection::new(config.clone()).unwrap();
+        handler(StreamOwned::new(connection, stream))
+    })
+}
+
+/// Reads a request head, returning an empty string if the connection is closed first.
+pub fn read_head(stream: &mut impl Read) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(1) => head.push(byte[0]),
+            _ => return String::new(),
+        }
+    }
+    String::from_utf8(head).unwrap()
+}
+
+/// Responds to a single request with `body`.
+pub fn respond(stream: &mut (impl Read + Write), body: &str) {
+    read_head(stream);
+    let response = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}", body.len(), body);
+    stream.write_all(response.as_bytes()).unwrap();
+    stream.flush().unwrap();
+}
+
+/// Encodes `der` as PEM with the given label.
+pub fn pem(label: &str, der: &[u8]) -> String {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    let encoded = STANDARD.encode(der);
+    let lines: Vec<&str> = encoded.as_bytes().chunks(64).map(|line| std::str::from_utf8(line).unwrap()).collect();
+    format!("-----BEGIN {label}-----\n{}\n-----END {label}-----\n", lines.join("\n"))
+}
+
+/// Returns a path in the temporary directory unique to this process.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("async-web-client-{}-{}", std::process::id(), name))
+}
+
+/// The crypto provider [`TlsConfigBuilder::new`](crate::TlsConfigBuilder::new) uses.
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+pub fn provider() -> Arc<CryptoProvider> {
+    #[cfg(all(feature = "ring", not(feature = "aws-lc-rs")))]
+    let provider = futures_rustls::rustls::crypto::ring::default_provider();
+    #[cfg(feature = "aws-lc-rs")]
+    let provider = futures_rustls::rustls::crypto::aws_lc_rs::default_provider();
+    Arc::new(provider)
+}
+
+/// Server config presenting `chain`, signed with `key`.
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+pub fn server_config(chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Arc<ServerConfig> {
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .unwrap();
+    Arc::new(config)
+}
+
+/// Sends a request to `localhost` served with `config`, returning the response body.
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+pub fn https_get(config: Arc<ServerConfig>, connector: crate::Connector) -> Result<String, crate::HttpError> {
+    use crate::RequestWithoutBodyExt;
+    let addr = serve_tls(config, |mut stream| respond(&mut stream, "ok"));
+    let request = http::Request::get(format!("https://localhost:{}/", addr.port())).body(()).unwrap();
+    futures::executor::block_on(async {
+        let mut response = request.send_with_connector((), connector).await?;
+        response
+            .body_mut()
+            .string(None)
+            .await
+            .map_err(|err| crate::HttpError::IoError(err.into()))
+    })
+}
+
+/// A certificate authority issuing certificates for tests.
+pub struct Ca {
+    pub certificate: Certificate,
+    pub key: KeyPair,
+}
+
+impl Ca {
+    pub fn new(name: &str) -> Self {
+        let key = KeyPair::generate().unwrap();
+        let certificate = Self::params(name).self_signed(&key).unwrap();
+        Self { certificate, key }
+    }
+    /// Issues an intermediate CA.
+    pub fn intermediate(&self, name: &str) -> Self {
+        let key = KeyPair::generate().unwrap();
+        let certificate = Self::params(name).signed_by(&key, &self.certificate, &self.key).unwrap();
+        Self { certificate, key }
+    }
+    /// Issues an end-entity certificate for `host` with the given parameters.
+    pub fn issue(&self, mut params: CertificateParams) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+        let key = KeyPair::generate().unwrap();
+        params.is_ca = IsCa::NoCa;
+        let certificate = params.signed_by(&key, &self.certificate, &self.key).unwrap();
+        let key = PrivatePkcs8KeyDer::from(key.serialize_der());
+        (certificate.der().clone(), key.into())
+    }
+    /// Issues an end-entity certificate for `host`.
+    pub fn leaf(&self, host: &str) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+        self.issue(CertificateParams::new(vec![host.to_string()]).unwrap())
+    }
+    pub fn der(&self) -> CertificateDer<'static> {
+        self.certificate.der().clone()
+    }
+    /// Issues a CRL revoking the certificates with the given serial numbers.
+    pub fn crl(&self, revoked: &[u64]) -> CertificateRevocationListDer<'static> {
+        let revoked_certs = revoked.iter().map(|serial| RevokedCertParams {
+            serial_number: SerialNumber::from(*serial),
+            revocation_time: date_time_ymd(2024, 1, 1),
+            reason_code: Some(RevocationReason::KeyCompromise),
+            invalidity_date: None,
+        });
+        let params = CertificateRevocationListParams {
+            this_update: date_time_ymd(2024, 1, 1),
+            next_update: date_time_ymd(2100, 1, 1),
+            crl_number: SerialNumber::from(1u64),
+            issuing_distribution_point: None,
+            revoked_certs: revoked_certs.collect(),
+            key_identifier_method: KeyIdMethod::Sha256,
+        };
+        params.signed_by(&self.certificate, &self.key).unwrap().der().clone()
+    }
+
+    fn params(name: &str) -> CertificateParams {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name.push(rcgen::DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        params
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use futures::future::{select, Either};
+
+/// Timeouts for the phases of a request. Unset timeouts never expire.
+///
+/// The connect timeout covers name resolution and TCP connection establishment, the response head
+/// timeout starts once the request has been sent and the body idle timeout applies to every read
+/// from the response body that has to wait for data. The total timeout covers the whole request,
+/// including reading the response body.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub tls_handshake: Option<Duration>,
+    pub response_head: Option<Duration>,
+    pub body_idle: Option<Duration>,
+    pub total: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    Connect,
+    TlsHandshake,
+    ResponseHead,
+    BodyIdle,
+    Total,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self {
+            TimeoutPhase::Connect => "connect",
+            TimeoutPhase::TlsHandshake => "tls handshake",
+            TimeoutPhase::ResponseHead => "response head",
+            TimeoutPhase::BodyIdle => "body idle",
+            TimeoutPhase::Total => "total",
+        };
+        f.write_str(phase)
+    }
+}
+
+pub(crate) async fn with_timeout<T>(timeout: Option<Duration>, phase: TimeoutPhase, future: impl Future<Output = T>) -> Result<T, TimeoutPhase> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Ok(future.await),
+    };
+    futures::pin_mut!(future);
+    match select(future, Timer::after(timeout)).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(phase),
+    }
+}
+
+/// Returns the deadline expiring first.
+pub(crate) fn earliest(a: Option<(TimeoutPhase, Instant)>, b: Option<(TimeoutPhase, Instant)>) -> Option<(TimeoutPhase, Instant)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.1 < a.1 { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Timer for state machines whose deadline changes between polls.
+#[derive(Default)]
+pub(crate) struct PhaseTimer {
+    timer: Option<(TimeoutPhase, Instant, Timer)>,
+}
+
+impl PhaseTimer {
+    pub fn poll(&mut self, cx: &mut Context<'_>, deadline: Option<(TimeoutPhase, Instant)>) -> Poll<TimeoutPhase> {
+        let (phase, at) = match deadline {
+            Some(deadline) => deadline,
+            None => {
+                self.timer = None;
+                return Poll::Pending;
+            }
+        };
+        if !matches!(&self.timer, Some((_, armed, _)) if *armed == at) {
+            self.timer = Some((phase, at, Timer::at(at)));
+        }
+        let (phase, _, timer) = self.timer.as_mut().unwrap();
+        match Pin::new(timer).poll(cx) {
+            Poll::Ready(_) => Poll::Ready(*phase),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::{
+        io::{self, Write},
+        net::SocketAddr,
+        thread,
+        time::Duration,
+    };
+
+    use futures::executor::block_on;
+    use http::Request;
+
+    use super::{TimeoutPhase, Timeouts};
+    use crate::test_util::{read_head, serve};
+    use crate::{Connector, HttpError, RequestWithoutBodyExt};
+
+    const SHORT: Option<Duration> = Some(Duration::from_millis(100));
+
+    /// Sends a request and reads the response body, returning the timeout it failed with.
+    fn timeout_phase(uri: &str, timeouts: Timeouts) -> TimeoutPhase {
+        let connector = Connector::default().with_timeouts(timeouts);
+        let request = Request::get(uri).body(()).unwrap();
+        let err = block_on(async {
+            let mut response = request.send_with_connector((), connector).await?;
+            response.body_mut().bytes(None).await?;
+            Ok::<_, io::Error>(())
+        })
+        .unwrap_err();
+        match err.get_ref().and_then(|err| err.downcast_ref::<HttpError>()) {
+            Some(HttpError::Timeout(phase)) => *phase,
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    /// Serves requests with a response head and the start of a body, then stalls.
+    fn stalling_body() -> SocketAddr {
+        serve(|mut stream| {
+            read_head(&mut stream);
+            stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nabc").unwrap();
+            thread::sleep(Duration::from_secs(5));
+        })
+    }
+
+    /// Serves requests by reading them without ever responding.
+    fn silent() -> SocketAddr {
+        serve(|mut stream| {
+            read_head(&mut stream);
+            thread::sleep(Duration::from_secs(5));
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn connect() {
+        use socket2::{Domain, Socket, Type};
+        // linux drops connection attempts once the accept queue of a listener is full
+        let listener = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        listener.bind(&SocketAddr::from(([127, 0, 0, 1], 0)).into()).unwrap();
+        listener.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap().as_socket().unwrap();
+        let _queued: Vec<_> = (0..2)
+            .filter_map(|_| std::net::TcpStream::connect_timeout(&addr, Duration::from_millis(100)).ok())
+            .collect();
+        let timeouts = Timeouts {
+            connect: SHORT,
+            ..Timeouts::default()
+        };
+        assert_eq!(timeout_phase(&format!("http://{}/", addr), timeouts), TimeoutPhase::Connect);
+    }
+
+    #[test]
+    fn tls_handshake() {
+        let timeouts = Timeouts {
+            tls_handshake: SHORT,
+            ..Timeouts::default()
+        };
+        assert_eq!(timeout_phase(&format!("https://{}/", silent()), timeouts), TimeoutPhase::TlsHandshake);
+    }
+
+    #[test]
+    fn response_head() {
+        let timeouts = Timeouts {
+            response_head: SHORT,
+            ..Timeouts::default()
+        };
+        assert_eq!(timeout_phase(&format!("http://{}/", silent()), timeouts), TimeoutPhase::ResponseHead);
+    }
+
+    #[test]
+    fn body_idle() {
+        let timeouts = Timeouts {
+            body_idle: SHORT,
+            ..Timeouts::default()
+        };
+        assert_eq!(timeout_phase(&format!("http://{}/", stalling_body()), timeouts), TimeoutPhase::BodyIdle);
+    }
+
+    #[test]
+    fn total() {
+        let addr = stalling_body();
+        let timeouts = Timeouts {
+            total: SHORT,
+            ..Timeouts::default()
+        };
+        assert_eq!(timeout_phase(&format!("http://{}/", addr), timeouts), TimeoutPhase::Total);
+        let timeouts = Timeouts {
+            total: SHORT,
+            response_head: Some(Duration::from_secs(5)),
+            ..Timeouts::default()
+        };
+        assert_eq!(timeout_phase(&format!("http://{}/", silent()), timeouts), TimeoutPhase::Total);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{path::Path, sync::Arc};
+
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+
+use super::{read_certificates, TlsConfigError};
+
+/// A client certificate chain and its private key, presented to servers requesting client authentication.
+pub struct ClientAuth {
+    pub(crate) certificates: Vec<CertificateDer<'static>>,
+    pub(crate) key: PrivateKeyDer<'static>,
+}
+
+impl ClientAuth {
+    pub fn new(certificates: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Self {
+        Self { certificates, key }
+    }
+    /// Reads a certificate chain (leaf first) and a PKCS#8, PKCS#1 (RSA) or SEC1 private key from PEM files.
+    /// Both may be contained in the same file.
+    pub fn from_pem_files(certificates: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, TlsConfigError> {
+        let certificates = read_certificates(certificates.as_ref())?;
+        let key = key.as_ref();
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|err| TlsConfigError::Pem(key.into(), Arc::new(err)))?;
+        Ok(Self { certificates, key })
+    }
+    /// Reads the first private key and its certificate chain from a PKCS#12 bundle.
+    #[cfg(feature = "pkcs12")]
+    pub fn from_pkcs12_file(path: impl AsRef<Path>, password: &str) -> Result<Self, TlsConfigError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|err| TlsConfigError::Io(path.into(), Arc::new(err)))?;
+        Self::from_pkcs12(&data, password)
+    }
+    #[cfg(feature = "pkcs12")]
+    pub fn from_pkcs12(data: &[u8], password: &str) -> Result<Self, TlsConfigError> {
+        let key_store = p12_keystore::KeyStore::from_pkcs12(data, password).map_err(|err| TlsConfigError::Pkcs12(err.to_string()))?;
+        let (_alias, chain) = key_store
+            .private_key_chain()
+            .ok_or_else(|| TlsConfigError::Pkcs12("no private key found".to_string()))?;
+        let certificates = chain.chain().iter().map(|cert| CertificateDer::from(cert.as_der().to_vec())).collect();
+        let key = PrivateKeyDer::Pkcs8(chain.key().to_vec().into());
+        Ok(Self { certificates, key })
+    }
+}
+
+impl Clone for ClientAuth {
+    fn clone(&self) -> Self {
+        Self {
+            certificates: self.certificates.clone(),
+            key: self.key.clone_key(),
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use futures::executor::block_on;
+    use futures_rustls::rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+    use http::Request;
+    use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+
+    use super::ClientAuth;
+    use crate::test_util::{pem, provider, respond, serve_tls, temp_path, Ca};
+    use crate::{Connector, HttpError, RequestWithoutBodyExt, TlsConfigBuilder, TlsConfigError};
+
+    /// Serves `localhost` with a certificate of `ca`, requiring client certificates issued by `ca`.
+    fn get(ca: &Ca, connector: Connector) -> Result<(), HttpError> {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der()).unwrap();
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider()).build().unwrap();
+        let (leaf, key) = ca.leaf("localhost");
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(vec![leaf], key)
+            .unwrap();
+        let addr = serve_tls(Arc::new(config), |mut stream| respond(&mut stream, "ok"));
+        let request = Request::get(format!("https://localhost:{}/", addr.port())).body(()).unwrap();
+        block_on(request.send_with_connector((), connector)).map(drop)
+    }
+
+    fn builder(ca: &Ca) -> TlsConfigBuilder {
+        TlsConfigBuilder::new().with_webpki_roots(false).with_root_certificate(ca.der())
+    }
+
+    fn pem_file(name: &str, certificate: &CertificateDer<'_>, key: &PrivateKeyDer<'_>) -> std::path::PathBuf {
+        let path = temp_path(name);
+        fs::write(&path, pem("CERTIFICATE", certificate) + &pem("PRIVATE KEY", key.secret_der())).unwrap();
+        path
+    }
+
+    #[test]
+    fn pem_files_per_host() {
+        let ca = Ca::new("root");
+        let (certificate, key) = ca.leaf("client");
+        let path = pem_file("client.pem", &certificate, &key);
+        let client_auth = ClientAuth::from_pem_files(&path, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(client_auth.certificates, [certificate]);
+
+        let without = builder(&ca).build().unwrap();
+        assert!(get(&ca, Connector::new(without.clone())).is_err());
+        let with = builder(&ca).with_client_auth(client_auth).build().unwrap();
+        get(&ca, Connector::new(with.clone())).unwrap();
+        get(&ca, Connector::new(without.clone()).with_host_client_config("localhost", with.clone())).unwrap();
+        assert!(get(&ca, Connector::new(without).with_host_client_config("example.com", with)).is_err());
+    }
+
+    #[test]
+    fn pem_file_errors() {
+        let missing = temp_path("missing-client.pem");
+        assert!(matches!(ClientAuth::from_pem_files(&missing, &missing), Err(TlsConfigError::Io(path, _)) if path == missing));
+        let ca = Ca::new("root");
+        let certificate_only = temp_path("certificate-only.pem");
+        fs::write(&certificate_only, pem("CERTIFICATE", &ca.der())).unwrap();
+        let result = ClientAuth::from_pem_files(&certificate_only, &certificate_only);
+        fs::remove_file(&certificate_only).unwrap();
+        assert!(matches!(result, Err(TlsConfigError::Pem(path, _)) if path == certificate_only));
+    }
+
+    #[cfg(feature = "pkcs12")]
+    #[test]
+    fn pkcs12() {
+        use p12_keystore::{Certificate, KeyStore, KeyStoreEntry, PrivateKeyChain};
+
+        let ca = Ca::new("root");
+        let (certificate, key) = ca.leaf("client");
+        let chain = [&certificate, &ca.der()].map(|certificate| Certificate::from_der(certificate).unwrap());
+        let mut key_store = KeyStore::new();
+        key_store.add_entry(
+            "client",
+            KeyStoreEntry::PrivateKeyChain(PrivateKeyChain::new(key.secret_der(), [1], chain)),
+        );
+        let bundle = key_store.writer("secret").write().unwrap();
+
+        let client_auth = ClientAuth::from_pkcs12(&bundle, "secret").unwrap();
+        assert_eq!(client_auth.certificates, [certificate, ca.der()]);
+        assert_eq!(client_auth.key.secret_der(), key.secret_der());
+        assert!(matches!(ClientAuth::from_pkcs12(&bundle, "wrong"), Err(TlsConfigError::Pkcs12(_))));
+        get(&ca, Connector::new(builder(&ca).with_client_auth(client_auth).build().unwrap())).unwrap();
+    }
+}
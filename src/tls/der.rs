@@ -0,0 +1,115 @@
+//! Minimal DER reader for the few X.509 certificate fields the crate needs.
+
+use std::time::SystemTime;
+
+use crate::date;
+
+const SEQUENCE: u8 = 0x30;
+const VERSION: u8 = 0xa0;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Reads the next element, returning its tag, its complete encoding and its contents.
+    fn next(&mut self) -> Option<(u8, &'a [u8], &'a [u8])> {
+        let (&tag, rest) = self.0.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (len, rest) = match first {
+            0..=0x7f => (first as usize, rest),
+            0x81..=0x84 => {
+                let count = (first & 0x7f) as usize;
+                let bytes = rest.get(..count)?;
+                (bytes.iter().fold(0usize, |len, b| len << 8 | *b as usize), &rest[count..])
+            }
+            _ => return None,
+        };
+        let contents = rest.get(..len)?;
+        let header = self.0.len() - rest.len();
+        let element = &self.0[..header + len];
+        self.0 = &rest[len..];
+        Some((tag, element, contents))
+    }
+
+    fn expect(&mut self, expected: u8) -> Option<(&'a [u8], &'a [u8])> {
+        let (tag, element, contents) = self.next()?;
+        (tag == expected).then_some((element, contents))
+    }
+}
+
+/// Returns the fields of the `TBSCertificate` starting at the serial number.
+fn tbs_certificate(certificate: &[u8]) -> Option<Reader<'_>> {
+    let (_, certificate) = Reader(certificate).expect(SEQUENCE)?;
+    let (_, tbs) = Reader(certificate).expect(SEQUENCE)?;
+    let mut tbs = Reader(tbs);
+    if tbs.0.first() == Some(&VERSION) {
+        tbs.next()?;
+    }
+    Some(tbs)
+}
+
+/// Returns the DER encoded `SubjectPublicKeyInfo` of a certificate.
+pub(crate) fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let mut tbs = tbs_certificate(certificate)?;
+    // serial number, signature algorithm, issuer, validity, subject
+    for _ in 0..5 {
+        tbs.next()?;
+    }
+    tbs.expect(SEQUENCE).map(|(element, _)| element)
+}
+
+/// Returns the end of the validity period (`notAfter`) of a certificate.
+pub(crate) fn not_after(certificate: &[u8]) -> Option<SystemTime> {
+    let mut tbs = tbs_certificate(certificate)?;
+    // serial number, signature algorithm, issuer
+    for _ in 0..3 {
+        tbs.next()?;
+    }
+    let (_, validity) = tbs.expect(SEQUENCE)?;
+    let mut validity = Reader(validity);
+    validity.next()?;
+    let (tag, _, time) = validity.next()?;
+    parse_time(tag, time)
+}
+
+/// Parses a `UTCTime` or `GeneralizedTime` in the `Z` terminated form required by RFC 5280.
+fn parse_time(tag: u8, time: &[u8]) -> Option<SystemTime> {
+    let (year, rest) = match (tag, time.len()) {
+        (UTC_TIME, 13) => {
+            let year = digits(&time[..2])? as i64;
+            (if year < 50 { 2000 + year } else { 1900 + year }, &time[2..])
+        }
+        (GENERALIZED_TIME, 15) => (digits(&time[..4])? as i64, &time[4..]),
+        _ => return None,
+    };
+    if rest[10] != b'Z' {
+        return None;
+    }
+    let [month, day, hour, minute, second] = [0, 2, 4, 6, 8].map(|i| digits(&rest[i..i + 2]));
+    date::from_utc(year, month?, day?, hour?, minute?, second?)
+}
+
+fn digits(digits: &[u8]) -> Option<u32> {
+    digits.iter().try_fold(0, |value, digit| match digit {
+        b'0'..=b'9' => Some(value * 10 + (digit - b'0') as u32),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{parse_time, GENERALIZED_TIME, UTC_TIME};
+
+    #[test]
+    fn parse_times() {
+        let at = |seconds| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(parse_time(UTC_TIME, b"700101000000Z"), at(0));
+        assert_eq!(parse_time(UTC_TIME, b"491231235959Z"), at(2524607999));
+        assert_eq!(parse_time(GENERALIZED_TIME, b"20240229120000Z"), at(1709208000));
+        assert_eq!(parse_time(UTC_TIME, b"240229120000+0100"), None);
+        assert_eq!(parse_time(GENERALIZED_TIME, b"2024022912000aZ"), None);
+    }
+}
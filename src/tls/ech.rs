@@ -0,0 +1,176 @@
+use std::{fs, io, path::Path, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_rustls::rustls::{internal::msgs::codec::Codec, Error, PeerIncompatible};
+use rustls_pki_types::EchConfigListBytes;
+
+use super::TlsConfigError;
+
+/// Reads an ECH config list, either binary or base64 encoded as in the `ech` parameter of DNS HTTPS records.
+pub(crate) fn read_ech_config_list(path: &Path) -> Result<EchConfigListBytes<'static>, TlsConfigError> {
+    let data = fs::read(path).map_err(|err| TlsConfigError::Io(path.into(), Arc::new(err)))?;
+    let decoded = std::str::from_utf8(&data).ok().and_then(|text| BASE64.decode(text.trim()).ok());
+    Ok(EchConfigListBytes::from(decoded.unwrap_or(data)))
+}
+
+/// Outcome of a handshake in which the server rejected ECH.
+pub(crate) enum EchRejection {
+    /// The server provided ECH configs to retry with.
+    Retry(EchConfigListBytes<'static>),
+    NoRetry,
+}
+
+impl EchRejection {
+    /// Extracts an ECH rejection from a TLS handshake error.
+    ///
+    /// rustls only exposes the retry configs as its internal message type, so they are encoded back into a
+    /// config list with its codec; `retry_configs_round_trip` catches changes to that encoding.
+    pub fn from_io_error(err: &io::Error) -> Option<Self> {
+        match err.get_ref()?.downcast_ref::<Error>()? {
+            Error::PeerIncompatible(PeerIncompatible::ServerRejectedEncryptedClientHello(retry_configs)) => match retry_configs {
+                Some(retry_configs) => {
+                    let mut list = Vec::new();
+                    retry_configs.encode(&mut list);
+                    Some(Self::Retry(list.into()))
+                }
+                None => Some(Self::NoRetry),
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, sync::Arc};
+
+    use base64::Engine;
+    use futures_rustls::rustls::internal::msgs::{codec::Codec, handshake::EchConfigPayload};
+    use futures_rustls::rustls::{Error, PeerIncompatible};
+
+    use super::{read_ech_config_list, EchRejection};
+    use crate::test_util::{https_get, server_config, temp_path, Ca};
+    use crate::{Connector, HttpError, TlsConfigBuilder, TlsConfigError, TransportError};
+
+    /// An ECH config list with a single X25519, HKDF-SHA256, AES-128-GCM config for `public_name`.
+    fn ech_config_list(public_name: &str) -> Vec<u8> {
+        let mut contents = vec![7, 0x00, 0x20, 0, 32];
+        contents.extend([0x42; 32]);
+        contents.extend([0, 4, 0x00, 0x01, 0x00, 0x01, 0, public_name.len() as u8]);
+        contents.extend(public_name.as_bytes());
+        contents.extend([0, 0]);
+        let mut config = vec![0xfe, 0x0d];
+        config.extend((contents.len() as u16).to_be_bytes());
+        config.extend(contents);
+        let mut list = (config.len() as u16).to_be_bytes().to_vec();
+        list.extend(config);
+        list
+    }
+
+    fn rejection(err: Error) -> Option<Option<Vec<u8>>> {
+        let err = io::Error::new(io::ErrorKind::InvalidData, err);
+        EchRejection::from_io_error(&err).map(|rejection| match rejection {
+            EchRejection::Retry(retry_configs) => Some(retry_configs.to_vec()),
+            EchRejection::NoRetry => None,
+        })
+    }
+
+    fn rejected(retry_configs: Option<Vec<EchConfigPayload>>) -> Error {
+        Error::PeerIncompatible(PeerIncompatible::ServerRejectedEncryptedClientHello(retry_configs))
+    }
+
+    #[test]
+    fn rejection_from_io_error() {
+        assert_eq!(rejection(rejected(None)), Some(None));
+        assert_eq!(rejection(rejected(Some(Vec::new()))), Some(Some(vec![0, 0])));
+        assert_eq!(rejection(Error::PeerIncompatible(PeerIncompatible::Tls12NotOffered)), None);
+        assert_eq!(rejection(Error::DecryptError), None);
+        assert!(EchRejection::from_io_error(&io::Error::other("other")).is_none());
+        assert!(EchRejection::from_io_error(&io::ErrorKind::ConnectionReset.into()).is_none());
+    }
+
+    #[test]
+    fn retry_configs_round_trip() {
+        let list = ech_config_list("retry.example");
+        let retry_configs = Vec::<EchConfigPayload>::read_bytes(&list).unwrap();
+        assert_eq!(rejection(rejected(Some(retry_configs))), Some(Some(list)));
+    }
+
+    #[test]
+    fn retry_rebuilds_host_config() {
+        let builder = TlsConfigBuilder::new().with_ech_config_list(ech_config_list("localhost").into());
+        let connector = Connector::new(TlsConfigBuilder::new().build().unwrap())
+            .with_host_ech("Localhost", builder)
+            .unwrap();
+        let initial = connector.client_config_for_host("localhost");
+        assert!(connector.retry_ech("other", ech_config_list("localhost").into()).is_none());
+
+        let retried = connector
+            .retry_ech("localhost", ech_config_list("retry.example").into())
+            .unwrap()
+            .unwrap();
+        assert!(!Arc::ptr_eq(&initial, &retried));
+        assert!(Arc::ptr_eq(&connector.client_config_for_host("localhost"), &retried));
+
+        let mut invalid = ech_config_list("retry.example");
+        invalid.truncate(invalid.len() - 3);
+        assert!(matches!(
+            connector.retry_ech("localhost", invalid.into()),
+            Some(Err(TlsConfigError::Rustls(_)))
+        ));
+        assert!(Arc::ptr_eq(&connector.client_config_for_host("localhost"), &retried));
+    }
+
+    #[test]
+    fn config_list_file() {
+        let list = ech_config_list("localhost");
+        let binary = temp_path("ech-binary");
+        let base64 = temp_path("ech-base64");
+        fs::write(&binary, &list).unwrap();
+        fs::write(&base64, format!("{}\n", super::BASE64.encode(&list))).unwrap();
+        assert_eq!(read_ech_config_list(&binary).unwrap().as_ref(), list);
+        assert_eq!(read_ech_config_list(&base64).unwrap().as_ref(), list);
+        assert!(TlsConfigBuilder::new().with_ech_config_list_file(&base64).build().is_ok());
+        fs::remove_file(binary).unwrap();
+        fs::remove_file(base64).unwrap();
+
+        let missing = temp_path("ech-missing");
+        let result = TlsConfigBuilder::new().with_ech_config_list_file(&missing).build();
+        assert!(matches!(result, Err(TlsConfigError::Io(path, _)) if path == missing));
+    }
+
+    #[test]
+    fn invalid_config_list() {
+        let mut list = ech_config_list("localhost");
+        list.truncate(list.len() - 3);
+        let result = TlsConfigBuilder::new().with_ech_config_list(list.into()).build();
+        assert!(matches!(result, Err(TlsConfigError::Rustls(_))));
+    }
+
+    #[test]
+    fn rejected_by_server() {
+        let ca = Ca::new("root");
+        let (leaf, key) = ca.leaf("localhost");
+        let builder = TlsConfigBuilder::new()
+            .with_root_certificate(ca.der())
+            .with_ech_config_list(ech_config_list("localhost").into());
+        let connector = Connector::new(builder.clone().build().unwrap());
+        let result = https_get(server_config(vec![leaf.clone()], key.clone_key()), connector);
+        assert!(
+            matches!(result, Err(HttpError::ConnectError(TransportError::EchRejected))),
+            "{:?}",
+            result
+        );
+
+        // A server without ECH support offers no retry configs, so the connection is not retried.
+        let connector = Connector::new(TlsConfigBuilder::new().build().unwrap())
+            .with_host_ech("localhost", builder)
+            .unwrap();
+        let result = https_get(server_config(vec![leaf], key), connector);
+        assert!(
+            matches!(result, Err(HttpError::ConnectError(TransportError::EchRejected))),
+            "{:?}",
+            result
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use futures_rustls::rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    CertificateError, DigitallySignedStruct, Error, SignatureScheme,
+};
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+
+/// Server certificate checks to skip. Only meant for development setups with self-signed certificates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DangerousVerification {
+    /// Accepts any certificate chain. Handshake signatures are still checked against the presented certificate.
+    NoCertificateVerification,
+    /// Validates the certificate chain against the trusted roots, but accepts it for any host name.
+    NoHostnameVerification,
+}
+
+#[derive(Debug)]
+pub(crate) struct InsecureVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    mode: DangerousVerification,
+}
+
+impl InsecureVerifier {
+    pub fn new(inner: Arc<dyn ServerCertVerifier>, mode: DangerousVerification) -> Self {
+        Self { inner, mode }
+    }
+}
+
+impl ServerCertVerifier for InsecureVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        match self.mode {
+            DangerousVerification::NoCertificateVerification => {
+                log::warn!("INSECURE: skipping tls certificate verification for {}", server_name.to_str());
+                Ok(ServerCertVerified::assertion())
+            }
+            DangerousVerification::NoHostnameVerification => {
+                log::warn!("INSECURE: skipping tls hostname verification for {}", server_name.to_str());
+                // webpki checks the host name last, so the rest of the chain has been validated
+                match self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+                    Err(Error::InvalidCertificate(CertificateError::NotValidForName))
+                    | Err(Error::InvalidCertificate(CertificateError::NotValidForNameContext { .. })) => Ok(ServerCertVerified::assertion()),
+                    result => result,
+                }
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use super::DangerousVerification;
+    use crate::test_util::{https_get, server_config, Ca};
+    use crate::{Connector, TlsConfigBuilder};
+
+    fn get(ca: &Ca, host: &str, trusted: bool, verification: Option<DangerousVerification>) -> bool {
+        let (leaf, key) = ca.leaf(host);
+        let mut builder = TlsConfigBuilder::new();
+        if trusted {
+            builder = builder.with_root_certificate(ca.der());
+        }
+        if let Some(verification) = verification {
+            builder = builder.with_dangerous_verification(verification);
+        }
+        https_get(server_config(vec![leaf], key), Connector::new(builder.build().unwrap())).is_ok()
+    }
+
+    #[test]
+    fn no_certificate_verification() {
+        let ca = Ca::new("untrusted");
+        assert!(!get(&ca, "localhost", false, None));
+        assert!(get(&ca, "localhost", false, Some(DangerousVerification::NoCertificateVerification)));
+        assert!(get(&ca, "example.com", false, Some(DangerousVerification::NoCertificateVerification)));
+    }
+
+    #[test]
+    fn no_hostname_verification() {
+        let ca = Ca::new("root");
+        assert!(get(&ca, "localhost", true, None));
+        assert!(!get(&ca, "example.com", true, None));
+        assert!(get(&ca, "example.com", true, Some(DangerousVerification::NoHostnameVerification)));
+        assert!(!get(&ca, "example.com", false, Some(DangerousVerification::NoHostnameVerification)));
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use futures_rustls::rustls::KeyLog;
+
+/// Appends TLS secrets to a file in the NSS key log format understood by Wireshark.
+pub(crate) struct KeyLogPath {
+    file: Mutex<File>,
+}
+
+impl KeyLogPath {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = Mutex::new(options.open(path)?);
+        Ok(Self { file })
+    }
+}
+
+impl KeyLog for KeyLogPath {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let line = format!("{} {} {}\n", label, hex(client_random), hex(secret));
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = file.write_all(line.as_bytes()) {
+            log::warn!("error writing tls key log: {}", err);
+        }
+    }
+}
+
+impl fmt::Debug for KeyLogPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyLogPath").finish_non_exhaustive()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use futures_rustls::rustls::KeyLog;
+
+    use super::KeyLogPath;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn nss_format() {
+        let path = temp_path("keylog.txt");
+        fs::write(&path, "# existing\n").unwrap();
+        let key_log = KeyLogPath::open(&path).unwrap();
+        key_log.log("CLIENT_RANDOM", &[0x00, 0xab], &[0x01, 0xff, 0x10]);
+        key_log.log("SERVER_TRAFFIC_SECRET_0", &[0x02], &[0x03]);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "# existing\nCLIENT_RANDOM 00ab 01ff10\nSERVER_TRAFFIC_SECRET_0 02 03\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = temp_path("keylog-new.txt");
+        let _ = fs::remove_file(&path);
+        KeyLogPath::open(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+    #[test]
+    fn handshake_secrets_logged() {
+        use futures::executor::block_on;
+        use http::Request;
+
+        use crate::test_util::{respond, serve_tls, server_config, Ca};
+        use crate::{Connector, RequestWithoutBodyExt, TlsConfigBuilder};
+
+        let ca = Ca::new("root");
+        let (leaf, key) = ca.leaf("localhost");
+        let addr = serve_tls(server_config(vec![leaf], key), |mut stream| respond(&mut stream, "ok"));
+        let path = temp_path("keylog-handshake.txt");
+        let config = TlsConfigBuilder::new()
+            .with_webpki_roots(false)
+            .with_root_certificate(ca.der())
+            .with_key_log_file(&path)
+            .build()
+            .unwrap();
+        let request = Request::get(format!("https://localhost:{}/", addr.port())).body(()).unwrap();
+        block_on(request.send_with_connector((), Connector::new(config))).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let labels: Vec<&str> = contents.lines().filter_map(|line| line.split(' ').next()).collect();
+        assert!(labels.contains(&"CLIENT_HANDSHAKE_TRAFFIC_SECRET"), "{}", contents);
+        assert!(labels.contains(&"CLIENT_TRAFFIC_SECRET_0"), "{}", contents);
+        assert!(contents.lines().all(|line| line.split(' ').count() == 3));
+    }
+}
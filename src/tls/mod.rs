@@ -0,0 +1,404 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures_rustls::rustls::{
+    client::{danger::ServerCertVerifier, Resumption, VerifierBuilderError, WebPkiServerVerifier},
+    crypto::CryptoProvider,
+    ClientConfig, KeyLog, RootCertStore,
+};
+use rustls_pki_types::{pem::PemObject, CertificateDer, TrustAnchor};
+use thiserror::Error;
+
+mod client_auth;
+pub(crate) mod der;
+#[cfg(feature = "aws-lc-rs")]
+mod ech;
+#[cfg(feature = "dangerous-insecure-tls")]
+mod insecure;
+mod key_log;
+mod pinning;
+mod revocation;
+
+pub use client_auth::ClientAuth;
+#[cfg(feature = "dangerous-insecure-tls")]
+pub use insecure::DangerousVerification;
+pub use pinning::{CertificatePins, PinMismatch, SpkiPin};
+pub use revocation::CertificateRevocation;
+
+#[cfg(feature = "aws-lc-rs")]
+use ech::read_ech_config_list;
+#[cfg(feature = "aws-lc-rs")]
+pub(crate) use ech::EchRejection;
+#[cfg(feature = "aws-lc-rs")]
+use futures_rustls::rustls::{
+    client::{EchConfig, EchMode},
+    crypto::aws_lc_rs::hpke,
+};
+#[cfg(feature = "dangerous-insecure-tls")]
+use insecure::InsecureVerifier;
+use key_log::KeyLogPath;
+use pinning::PinningVerifier;
+#[cfg(feature = "aws-lc-rs")]
+use rustls_pki_types::EchConfigListBytes;
+
+#[derive(Error, Debug, Clone)]
+pub enum TlsConfigError {
+    #[error("io error reading {0:?}: {1:?}")]
+    Io(PathBuf, Arc<io::Error>),
+    #[error("invalid pem in {0:?}: {1}")]
+    Pem(PathBuf, Arc<rustls_pki_types::pem::Error>),
+    #[error("no root certificates")]
+    NoRootCertificates,
+    #[error("invalid pkcs12 bundle: {0}")]
+    Pkcs12(String),
+    #[error("invalid certificate pin: {0:?}")]
+    InvalidPin(String),
+    #[error("certificate verifier error: {0}")]
+    Verifier(#[from] VerifierBuilderError),
+    #[error("tls error: {0}")]
+    Rustls(#[from] futures_rustls::rustls::Error),
+}
+
+/// Builds rustls client configs for use with [`Connector`](crate::Connector).
+///
+/// The bundled webpki roots are trusted by default and key logging is disabled unless explicitly enabled.
+/// Built configs advertise `http/1.1` via ALPN.
+#[derive(Clone)]
+pub struct TlsConfigBuilder {
+    provider: Arc<CryptoProvider>,
+    webpki_roots: bool,
+    system_roots: bool,
+    root_files: Vec<PathBuf>,
+    roots: Vec<CertificateDer<'static>>,
+    client_auth: Option<ClientAuth>,
+    pins: Option<CertificatePins>,
+    key_log: Option<KeyLogTarget>,
+    revocation: Option<CertificateRevocation>,
+    early_data: bool,
+    #[cfg(feature = "dangerous-insecure-tls")]
+    dangerous_verification: Option<DangerousVerification>,
+    #[cfg(feature = "aws-lc-rs")]
+    ech: Option<EchSource>,
+}
+
+#[derive(Clone)]
+enum KeyLogTarget {
+    Env,
+    Path(PathBuf),
+    Custom(Arc<dyn KeyLog>),
+}
+
+#[cfg(feature = "aws-lc-rs")]
+#[derive(Clone)]
+enum EchSource {
+    File(PathBuf),
+    List(EchConfigListBytes<'static>),
+}
+
+impl TlsConfigBuilder {
+    #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+    pub fn new() -> Self {
+        #[cfg(all(feature = "ring", not(feature = "aws-lc-rs")))]
+        let provider = futures_rustls::rustls::crypto::ring::default_provider();
+        #[cfg(feature = "aws-lc-rs")]
+        let provider = futures_rustls::rustls::crypto::aws_lc_rs::default_provider();
+        Self::new_with_provider(Arc::new(provider))
+    }
+    pub fn new_with_provider(provider: Arc<CryptoProvider>) -> Self {
+        Self {
+            provider,
+            webpki_roots: true,
+            system_roots: false,
+            root_files: Vec::new(),
+            roots: Vec::new(),
+            client_auth: None,
+            pins: None,
+            key_log: None,
+            revocation: None,
+            early_data: false,
+            #[cfg(feature = "dangerous-insecure-tls")]
+            dangerous_verification: None,
+            #[cfg(feature = "aws-lc-rs")]
+            ech: None,
+        }
+    }
+    /// Trusts the root certificates bundled with the `webpki-roots` crate.
+    pub fn with_webpki_roots(mut self, enabled: bool) -> Self {
+        self.webpki_roots = enabled;
+        self
+    }
+    /// Trusts the root certificates of the platform trust store. On Linux this honours `SSL_CERT_FILE`
+    /// and `SSL_CERT_DIR` and otherwise uses the usual locations like `/etc/ssl/certs`.
+    pub fn with_system_roots(mut self, enabled: bool) -> Self {
+        self.system_roots = enabled;
+        self
+    }
+    /// Trusts the certificates in a PEM or DER encoded file. The file is read by [`Self::build`].
+    pub fn with_root_certificates_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.root_files.push(path.into());
+        self
+    }
+    pub fn with_root_certificate(mut self, certificate: CertificateDer<'static>) -> Self {
+        self.roots.push(certificate);
+        self
+    }
+    /// Presents the given client certificate to servers requesting client authentication.
+    pub fn with_client_auth(mut self, client_auth: ClientAuth) -> Self {
+        self.client_auth = Some(client_auth);
+        self
+    }
+    /// Rejects revoked server certificates based on CRLs.
+    pub fn with_certificate_revocation(mut self, revocation: CertificateRevocation) -> Self {
+        self.revocation = Some(revocation);
+        self
+    }
+    /// Checks the certificates presented by the pinned hosts against their public key pins.
+    pub fn with_certificate_pins(mut self, pins: CertificatePins) -> Self {
+        self.pins = Some(pins);
+        self
+    }
+    /// Writes TLS secrets to the file named by the `SSLKEYLOGFILE` environment variable, if set when
+    /// [`Self::build`] is called. Anyone able to read the file can decrypt the traffic, so only enable
+    /// this for debugging.
+    pub fn with_key_log_from_env(mut self) -> Self {
+        self.key_log = Some(KeyLogTarget::Env);
+        self
+    }
+    /// Appends TLS secrets to `path` in the NSS key log format, e.g. for decrypting captures in Wireshark.
+    pub fn with_key_log_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.key_log = Some(KeyLogTarget::Path(path.into()));
+        self
+    }
+    /// Disables server certificate or host name verification. Every connection using the built config logs
+    /// a warning, session resumption is disabled so that no handshake skips it. Never use this outside of
+    /// development setups.
+    #[cfg(feature = "dangerous-insecure-tls")]
+    pub fn with_dangerous_verification(mut self, verification: DangerousVerification) -> Self {
+        self.dangerous_verification = Some(verification);
+        self
+    }
+    /// Allows sending TLS 1.3 early data on resumed sessions. Only connectors with early data enabled
+    /// make use of it, see [`Connector::with_early_data`](crate::Connector::with_early_data).
+    #[cfg(feature = "early-data")]
+    pub fn with_early_data(mut self, enabled: bool) -> Self {
+        self.early_data = enabled;
+        self
+    }
+    /// Encrypts the client hello using a config from `ech_config_list`, hiding the server name from on-path
+    /// observers. Built configs only support TLS 1.3 and are specific to the servers publishing the list,
+    /// so they are usually passed to [`Connector::with_host_ech`](crate::Connector::with_host_ech).
+    ///
+    /// Only available with the `aws-lc-rs` feature, as rustls does not support ECH with `ring`.
+    #[cfg(feature = "aws-lc-rs")]
+    pub fn with_ech_config_list(mut self, ech_config_list: EchConfigListBytes<'static>) -> Self {
+        self.ech = Some(EchSource::List(ech_config_list));
+        self
+    }
+    /// Reads the ECH config list from a file, either binary or base64 encoded as in the `ech` parameter of
+    /// DNS HTTPS records. The file is read by [`Self::build`]. Like [`Self::with_ech_config_list`], only
+    /// available with the `aws-lc-rs` feature.
+    #[cfg(feature = "aws-lc-rs")]
+    pub fn with_ech_config_list_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ech = Some(EchSource::File(path.into()));
+        self
+    }
+    /// Passes TLS secrets to a custom [`KeyLog`] implementation.
+    pub fn with_key_log(mut self, key_log: Arc<dyn KeyLog>) -> Self {
+        self.key_log = Some(KeyLogTarget::Custom(key_log));
+        self
+    }
+
+    pub fn build(self) -> Result<Arc<ClientConfig>, TlsConfigError> {
+        let root_store = Arc::new(self.root_store()?);
+        let mut sessions = None;
+        let mut verifier: Arc<dyn ServerCertVerifier> = match &self.revocation {
+            Some(revocation) => {
+                let verifier = revocation.verifier(root_store.clone(), self.provider.clone())?;
+                sessions = Some(verifier.sessions());
+                verifier
+            }
+            None => WebPkiServerVerifier::builder_with_provider(root_store.clone(), self.provider.clone()).build()?,
+        };
+        #[cfg(feature = "dangerous-insecure-tls")]
+        if let Some(verification) = self.dangerous_verification {
+            verifier = Arc::new(InsecureVerifier::new(verifier, verification));
+        }
+        if let Some(pins) = self.pins {
+            let algorithms = self.provider.signature_verification_algorithms;
+            verifier = Arc::new(PinningVerifier::new(verifier, pins, root_store, algorithms));
+        }
+        let builder = ClientConfig::builder_with_provider(self.provider);
+        #[cfg(feature = "aws-lc-rs")]
+        let builder = match self.ech {
+            Some(ech) => {
+                let ech_config_list = match ech {
+                    EchSource::File(path) => read_ech_config_list(&path)?,
+                    EchSource::List(ech_config_list) => ech_config_list,
+                };
+                let ech_config = EchConfig::new(ech_config_list, hpke::ALL_SUPPORTED_SUITES)?;
+                builder.with_ech(EchMode::Enable(ech_config))?
+            }
+            None => builder.with_safe_default_protocol_versions()?,
+        };
+        #[cfg(not(feature = "aws-lc-rs"))]
+        let builder = builder.with_safe_default_protocol_versions()?;
+        let builder = builder.dangerous().with_custom_certificate_verifier(verifier);
+        let mut config = match self.client_auth {
+            Some(ClientAuth { certificates, key }) => builder.with_client_auth_cert(certificates, key)?,
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols.push(b"http/1.1".to_vec());
+        config.enable_early_data = self.early_data;
+        if let Some(store) = sessions {
+            config.resumption = Resumption::store(store);
+        }
+        #[cfg(feature = "dangerous-insecure-tls")]
+        if self.dangerous_verification.is_some() {
+            config.resumption = Resumption::disabled();
+        }
+        let key_log_path = match self.key_log {
+            Some(KeyLogTarget::Env) => std::env::var_os("SSLKEYLOGFILE").map(PathBuf::from),
+            Some(KeyLogTarget::Path(path)) => Some(path),
+            Some(KeyLogTarget::Custom(key_log)) => {
+                config.key_log = key_log;
+                None
+            }
+            None => None,
+        };
+        if let Some(path) = key_log_path {
+            let key_log = KeyLogPath::open(&path).map_err(|err| TlsConfigError::Io(path.clone(), Arc::new(err)))?;
+            log::warn!("tls key logging enabled, writing secrets to {:?}", path);
+            config.key_log = Arc::new(key_log);
+        }
+        Ok(Arc::new(config))
+    }
+
+    fn root_store(&self) -> Result<RootCertStore, TlsConfigError> {
+        let mut root_store = RootCertStore::empty();
+        if self.webpki_roots {
+            root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().map(|t| {
+                let t = t.to_owned();
+                TrustAnchor {
+                    subject: t.subject,
+                    subject_public_key_info: t.subject_public_key_info,
+                    name_constraints: t.name_constraints,
+                }
+            }));
+        }
+        if self.system_roots {
+            let result = rustls_native_certs::load_native_certs();
+            for err in &result.errors {
+                log::warn!("error loading system root certificates: {}", err);
+            }
+            let (_, ignored) = root_store.add_parsable_certificates(result.certs);
+            if ignored > 0 {
+                log::warn!("ignored {} unparsable system root certificates", ignored);
+            }
+        }
+        for path in &self.root_files {
+            for certificate in read_certificates(path)? {
+                root_store.add(certificate)?;
+            }
+        }
+        for certificate in &self.roots {
+            root_store.add(certificate.clone())?;
+        }
+        if root_store.is_empty() {
+            return Err(TlsConfigError::NoRootCertificates);
+        }
+        Ok(root_store)
+    }
+}
+
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+impl Default for TlsConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads all certificates from a PEM file or a single certificate from a DER file.
+pub(crate) fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsConfigError> {
+    read_pem_or_der(path)
+}
+
+/// Reads all items of type `T` from a PEM file or a single item from a DER file.
+pub(crate) fn read_pem_or_der<T: PemObject + From<Vec<u8>>>(path: &Path) -> Result<Vec<T>, TlsConfigError> {
+    let data = fs::read(path).map_err(|err| TlsConfigError::Io(path.into(), Arc::new(err)))?;
+    if data.first() == Some(&0x30) {
+        return Ok(vec![T::from(data)]);
+    }
+    let items = T::pem_slice_iter(&data)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| TlsConfigError::Pem(path.into(), Arc::new(err)))?;
+    if items.is_empty() {
+        return Err(TlsConfigError::Pem(path.into(), Arc::new(rustls_pki_types::pem::Error::NoItemsFound)));
+    }
+    Ok(items)
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use futures_rustls::rustls::ClientConfig;
+
+    use super::{TlsConfigBuilder, TlsConfigError};
+    use crate::test_util::{https_get, server_config, temp_path, Ca};
+    use crate::Connector;
+
+    /// Sends a request to a server presenting a certificate for `localhost` issued by `ca`.
+    fn get_ok(ca: &Ca, client_config: Arc<ClientConfig>) -> Result<String, crate::HttpError> {
+        let (leaf, key) = ca.leaf("localhost");
+        https_get(server_config(vec![leaf], key), Connector::new(client_config))
+    }
+
+    #[test]
+    fn custom_roots() {
+        let ca = Ca::new("root");
+        let config = TlsConfigBuilder::new()
+            .with_webpki_roots(false)
+            .with_root_certificate(ca.der())
+            .build()
+            .unwrap();
+        assert_eq!(config.alpn_protocols, [b"http/1.1".to_vec()]);
+        assert_eq!(get_ok(&ca, config).unwrap(), "ok");
+
+        let pem = temp_path("roots.pem");
+        let der = temp_path("root.der");
+        fs::write(&pem, Ca::new("other").certificate.pem() + &ca.certificate.pem()).unwrap();
+        fs::write(&der, ca.der()).unwrap();
+        for path in [&pem, &der] {
+            let config = TlsConfigBuilder::new()
+                .with_webpki_roots(false)
+                .with_root_certificates_file(path)
+                .build()
+                .unwrap();
+            assert_eq!(get_ok(&ca, config).unwrap(), "ok");
+        }
+        let config = TlsConfigBuilder::new().build().unwrap();
+        assert!(get_ok(&ca, config).is_err());
+        fs::remove_file(pem).unwrap();
+        fs::remove_file(der).unwrap();
+    }
+
+    #[test]
+    fn build_errors() {
+        let result = TlsConfigBuilder::new().with_webpki_roots(false).build();
+        assert!(matches!(result, Err(TlsConfigError::NoRootCertificates)));
+        let missing = temp_path("missing.pem");
+        let result = TlsConfigBuilder::new().with_root_certificates_file(&missing).build();
+        assert!(matches!(result, Err(TlsConfigError::Io(path, _)) if path == missing));
+        let invalid = temp_path("invalid.pem");
+        fs::write(&invalid, "no certificates here").unwrap();
+        let result = TlsConfigBuilder::new().with_root_certificates_file(&invalid).build();
+        assert!(matches!(result, Err(TlsConfigError::Pem(path, _)) if path == invalid));
+        fs::remove_file(invalid).unwrap();
+        let not_a_certificate = rustls_pki_types::CertificateDer::from(vec![0x30, 0x03, 0x02, 0x01, 0x01]);
+        let result = TlsConfigBuilder::new().with_root_certificate(not_a_certificate).build();
+        assert!(matches!(result, Err(TlsConfigError::Rustls(_))), "{:?}", result.err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::{collections::HashMap, error::Error as StdError, fmt, io, net::IpAddr, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_rustls::rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::WebPkiSupportedAlgorithms,
+    CertificateError, DigitallySignedStruct, Error, OtherError, RootCertStore, SignatureScheme,
+};
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+use sha2::{Digest, Sha256};
+
+use super::{der, TlsConfigError};
+use crate::http::normalize_host;
+
+/// SHA-256 hash of a DER encoded `SubjectPublicKeyInfo`, the format also used by `pin-sha256` in HPKP.
+///
+/// Can be computed with
+/// `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpkiPin([u8; 32]);
+
+impl SpkiPin {
+    pub fn from_sha256(hash: [u8; 32]) -> Self {
+        Self(hash)
+    }
+    pub fn from_base64(pin: &str) -> Result<Self, TlsConfigError> {
+        let hash = BASE64.decode(pin).ok().and_then(|hash| hash.try_into().ok());
+        hash.map(Self).ok_or_else(|| TlsConfigError::InvalidPin(pin.to_string()))
+    }
+    pub fn from_certificate(certificate: &CertificateDer<'_>) -> Option<Self> {
+        der::subject_public_key_info(certificate).map(|spki| Self(Sha256::digest(spki).into()))
+    }
+}
+
+impl fmt::Debug for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SpkiPin({})", BASE64.encode(self.0))
+    }
+}
+
+/// Public key pins per host, checked against the certificates presented by the server.
+///
+/// Configure more than one pin per host (e.g. the pin of a backup key) so that a key rotation does not
+/// lock clients out. Hosts without pins are validated as usual.
+#[derive(Clone, Debug, Default)]
+pub struct CertificatePins {
+    hosts: HashMap<String, HostPins>,
+}
+
+#[derive(Clone, Debug)]
+struct HostPins {
+    pins: Vec<SpkiPin>,
+    webpki: bool,
+}
+
+impl CertificatePins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Requires the end-entity certificate or an intermediate CA certificate of the validated chain of `host`
+    /// to match one of the pins, in addition to the usual WebPKI validation. Certificates sent by the server
+    /// that are not part of the chain to a trusted root are ignored.
+    pub fn with_pins(mut self, host: impl Into<String>, pins: impl IntoIterator<Item = SpkiPin>) -> Self {
+        let pins = pins.into_iter().collect();
+        self.hosts.insert(normalize_host(&host.into()), HostPins { pins, webpki: true });
+        self
+    }
+    /// Requires the end-entity certificate presented by `host` to match one of the pins and skips WebPKI
+    /// validation for that host, so e.g. self-signed or expired certificates are accepted if pinned.
+    pub fn with_pins_replacing_webpki(mut self, host: impl Into<String>, pins: impl IntoIterator<Item = SpkiPin>) -> Self {
+        let pins = pins.into_iter().collect();
+        self.hosts.insert(normalize_host(&host.into()), HostPins { pins, webpki: false });
+        self
+    }
+}
+
+/// No certificate presented by the server matched the pins configured for the host.
+#[derive(Debug, Clone)]
+pub struct PinMismatch {
+    pub host: String,
+    /// Pins of the end-entity certificate and the intermediates of its validated chain.
+    pub presented: Vec<SpkiPin>,
+}
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "certificate pin mismatch for {}", self.host)
+    }
+}
+
+impl StdError for PinMismatch {}
+
+impl PinMismatch {
+    /// Extracts a pin mismatch from a TLS handshake error.
+    pub(crate) fn from_io_error(err: &io::Error) -> Option<Self> {
+        match err.get_ref()?.downcast_ref::<Error>()? {
+            Error::InvalidCertificate(CertificateError::Other(OtherError(other))) => other.downcast_ref::<PinMismatch>().cloned(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PinningVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: CertificatePins,
+    roots: Arc<RootCertStore>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinningVerifier {
+    pub fn new(inner: Arc<dyn ServerCertVerifier>, pins: CertificatePins, roots: Arc<RootCertStore>, algorithms: WebPkiSupportedAlgorithms) -> Self {
+        Self {
+            inner,
+            pins,
+            roots,
+            algorithms,
+        }
+    }
+
+    /// Returns the pins of `end_entity` and the intermediates of its path to a trusted root. Only
+    /// `end_entity` is pinned if no path can be built, e.g. when verification is dangerously disabled.
+    fn validated_pins(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], now: UnixTime) -> Vec<SpkiPin> {
+        let mut pins: Vec<SpkiPin> = SpkiPin::from_certificate(end_entity).into_iter().collect();
+        let Ok(cert) = webpki::EndEntityCert::try_from(end_entity) else {
+            return pins;
+        };
+        let usage = webpki::KeyUsage::server_auth();
+        match cert.verify_for_usage(self.algorithms.all, &self.roots.roots, intermediates, now, usage, None, None) {
+            Ok(path) => pins.extend(path.intermediate_certificates().filter_map(|cert| SpkiPin::from_certificate(&cert.der()))),
+            Err(err) => log::debug!("no validated chain to check pins against: {}", err),
+        }
+        pins
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            ServerName::IpAddress(ip) => IpAddr::from(*ip).to_string(),
+            _ => String::new(),
+        };
+        let host_pins = match self.pins.hosts.get(&host) {
+            Some(host_pins) => host_pins,
+            None => return self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now),
+        };
+        let presented: Vec<SpkiPin> = match host_pins.webpki {
+            true => {
+                self.inner
+                    .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+                self.validated_pins(end_entity, intermediates, now)
+            }
+            false => SpkiPin::from_certificate(end_entity).into_iter().collect(),
+        };
+        if presented.iter().any(|pin| host_pins.pins.contains(pin)) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        let mismatch = PinMismatch { host, presented };
+        Err(Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(mismatch)))))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_rustls::rustls::{client::WebPkiServerVerifier, CertificateError, Error, OtherError, RootCertStore};
+    use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+
+    use super::{CertificatePins, PinMismatch, PinningVerifier, SpkiPin};
+    use crate::test_util::{provider, Ca};
+
+    fn verify(roots: &[&Ca], pins: CertificatePins, chain: &[CertificateDer<'static>]) -> Result<(), Error> {
+        let mut root_store = RootCertStore::empty();
+        for root in roots {
+            root_store.add(root.der()).unwrap();
+        }
+        let root_store = Arc::new(root_store);
+        let inner = WebPkiServerVerifier::builder_with_provider(root_store.clone(), provider())
+            .build()
+            .unwrap();
+        let verifier = PinningVerifier::new(inner, pins, root_store, provider().signature_verification_algorithms);
+        let server_name = ServerName::try_from("example.com").unwrap();
+        let (end_entity, intermediates) = chain.split_first().unwrap();
+        let result = super::ServerCertVerifier::verify_server_cert(&verifier, end_entity, intermediates, &server_name, &[], UnixTime::now());
+        result.map(|_| ())
+    }
+
+    fn is_mismatch(err: &Error) -> bool {
+        matches!(err, Error::InvalidCertificate(CertificateError::Other(OtherError(other))) if other.is::<PinMismatch>())
+    }
+
+    #[test]
+    fn pins_match_validated_chain() {
+        let root = Ca::new("root");
+        let pinned = root.intermediate("pinned intermediate");
+        let pins = CertificatePins::new().with_pins("example.com", SpkiPin::from_certificate(&pinned.der()));
+        let (leaf, _) = pinned.leaf("example.com");
+        assert!(verify(&[&root], pins.clone(), &[leaf, pinned.der()]).is_ok());
+
+        // a chain of another trusted issuer carrying the pinned certificate as an unrelated intermediate
+        let other = Ca::new("other root");
+        let (leaf, _) = other.leaf("example.com");
+        let err = verify(&[&root, &other], pins, &[leaf, pinned.der()]).unwrap_err();
+        assert!(is_mismatch(&err), "{:?}", err);
+    }
+
+    #[test]
+    fn idna_hosts() {
+        let pins = CertificatePins::new()
+            .with_pins("Bücher.example", [])
+            .with_pins_replacing_webpki("STRASSE.example", []);
+        assert!(pins.hosts["xn--bcher-kva.example"].webpki);
+        assert!(!pins.hosts["strasse.example"].webpki);
+    }
+
+    #[test]
+    fn pins_replacing_webpki() {
+        let untrusted = Ca::new("untrusted");
+        let (leaf, _) = untrusted.leaf("example.com");
+        let chain = [leaf, untrusted.der()];
+        let pins = CertificatePins::new().with_pins_replacing_webpki("example.com", SpkiPin::from_certificate(&chain[0]));
+        assert!(verify(&[&Ca::new("root")], pins, &chain[..1]).is_ok());
+        let pins = CertificatePins::new().with_pins_replacing_webpki("example.com", SpkiPin::from_certificate(&chain[1]));
+        let err = verify(&[&Ca::new("root")], pins, &chain).unwrap_err();
+        assert!(is_mismatch(&err), "{:?}", err);
+    }
+}
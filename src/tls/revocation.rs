@@ -0,0 +1,296 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock, Weak},
+};
+
+use futures_rustls::rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        ClientSessionMemoryCache, ClientSessionStore, Tls12ClientSessionValue, Tls13ClientSessionValue, WebPkiServerVerifier,
+    },
+    crypto::CryptoProvider,
+    DigitallySignedStruct, Error, NamedGroup, RootCertStore, SignatureScheme,
+};
+use rustls_pki_types::{CertificateDer, CertificateRevocationListDer, ServerName, UnixTime};
+
+use super::{read_pem_or_der, TlsConfigError};
+
+/// Certificate revocation checking against CRLs.
+///
+/// By default the whole chain is checked, certificates whose revocation status cannot be determined
+/// (no CRL of their issuer is known) are rejected and expired CRLs are still used. CRL files are read when
+/// a config is built and again on [`Self::refresh`], which updates every config built with this instance
+/// or one of its clones. Without any CRLs no revocation checking takes place.
+///
+/// Resumed TLS sessions skip certificate verification, so configs checking revocation use a session cache
+/// that is cleared on refresh.
+#[derive(Clone, Default)]
+pub struct CertificateRevocation {
+    files: Vec<PathBuf>,
+    crls: Vec<CertificateRevocationListDer<'static>>,
+    end_entity_only: bool,
+    allow_unknown_status: bool,
+    reject_expired_crls: bool,
+    verifiers: Arc<Mutex<Vec<Weak<RevocationVerifier>>>>,
+}
+
+impl CertificateRevocation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Reads CRLs from a PEM or DER encoded file.
+    pub fn with_crl_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.files.push(path.into());
+        self
+    }
+    pub fn with_crl(mut self, crl: CertificateRevocationListDer<'static>) -> Self {
+        self.crls.push(crl);
+        self
+    }
+    /// Only checks the revocation status of the end-entity certificate.
+    pub fn with_end_entity_only(mut self, enabled: bool) -> Self {
+        self.end_entity_only = enabled;
+        self
+    }
+    /// Accepts certificates whose revocation status is unknown.
+    pub fn with_unknown_status_allowed(mut self, allowed: bool) -> Self {
+        self.allow_unknown_status = allowed;
+        self
+    }
+    /// Rejects certificates checked against a CRL whose next update time has passed.
+    pub fn with_expired_crls_rejected(mut self, rejected: bool) -> Self {
+        self.reject_expired_crls = rejected;
+        self
+    }
+
+    /// Re-reads the CRL files and updates all configs built with this instance. On error the previously
+    /// loaded CRLs stay in use.
+    pub fn refresh(&self) -> Result<(), TlsConfigError> {
+        let crls = self.load()?;
+        let mut verifiers = self.verifiers.lock().unwrap();
+        verifiers.retain(|verifier| verifier.strong_count() > 0);
+        let verifiers: Vec<_> = verifiers.iter().filter_map(Weak::upgrade).collect();
+        let rebuilt = verifiers
+            .iter()
+            .map(|verifier| self.webpki_verifier(&verifier.roots, &verifier.provider, crls.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (verifier, inner) in verifiers.iter().zip(rebuilt) {
+            *verifier.inner.write().unwrap() = inner;
+            verifier.sessions.clear();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn verifier(&self, roots: Arc<RootCertStore>, provider: Arc<CryptoProvider>) -> Result<Arc<RevocationVerifier>, TlsConfigError> {
+        let inner = self.webpki_verifier(&roots, &provider, self.load()?)?;
+        let verifier = Arc::new(RevocationVerifier {
+            roots,
+            provider,
+            inner: RwLock::new(inner),
+            sessions: Arc::new(SessionCache::default()),
+        });
+        self.verifiers.lock().unwrap().push(Arc::downgrade(&verifier));
+        Ok(verifier)
+    }
+
+    fn webpki_verifier(
+        &self,
+        roots: &Arc<RootCertStore>,
+        provider: &Arc<CryptoProvider>,
+        crls: Vec<CertificateRevocationListDer<'static>>,
+    ) -> Result<Arc<WebPkiServerVerifier>, TlsConfigError> {
+        let mut builder = WebPkiServerVerifier::builder_with_provider(roots.clone(), provider.clone()).with_crls(crls);
+        if self.end_entity_only {
+            builder = builder.only_check_end_entity_revocation();
+        }
+        if self.allow_unknown_status {
+            builder = builder.allow_unknown_revocation_status();
+        }
+        if self.reject_expired_crls {
+            builder = builder.enforce_revocation_expiration();
+        }
+        Ok(builder.build()?)
+    }
+
+    fn load(&self) -> Result<Vec<CertificateRevocationListDer<'static>>, TlsConfigError> {
+        let mut crls = self.crls.clone();
+        for path in &self.files {
+            crls.extend(read_pem_or_der(path)?);
+        }
+        Ok(crls)
+    }
+}
+
+/// Verifier whose CRLs can be replaced after the config using it has been built.
+#[derive(Debug)]
+pub(crate) struct RevocationVerifier {
+    roots: Arc<RootCertStore>,
+    provider: Arc<CryptoProvider>,
+    inner: RwLock<Arc<WebPkiServerVerifier>>,
+    sessions: Arc<SessionCache>,
+}
+
+impl RevocationVerifier {
+    fn inner(&self) -> Arc<WebPkiServerVerifier> {
+        self.inner.read().unwrap().clone()
+    }
+    /// Session cache to use with this verifier, cleared whenever the CRLs are refreshed.
+    pub fn sessions(&self) -> Arc<dyn ClientSessionStore> {
+        self.sessions.clone()
+    }
+}
+
+impl ServerCertVerifier for RevocationVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.inner()
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner().verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner().verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner().supported_verify_schemes()
+    }
+}
+
+/// Session cache that can be cleared, sized like the default rustls cache.
+#[derive(Debug)]
+struct SessionCache(RwLock<ClientSessionMemoryCache>);
+
+impl Default for SessionCache {
+    fn default() -> Self {
+        Self(RwLock::new(ClientSessionMemoryCache::new(256)))
+    }
+}
+
+impl SessionCache {
+    fn clear(&self) {
+        *self.0.write().unwrap() = ClientSessionMemoryCache::new(256);
+    }
+}
+
+impl ClientSessionStore for SessionCache {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.0.read().unwrap().set_kx_hint(server_name, group)
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.0.read().unwrap().kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: ServerName<'static>, value: Tls12ClientSessionValue) {
+        self.0.read().unwrap().set_tls12_session(server_name, value)
+    }
+
+    fn tls12_session(&self, server_name: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+        self.0.read().unwrap().tls12_session(server_name)
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.0.read().unwrap().remove_tls12_session(server_name)
+    }
+
+    fn insert_tls13_ticket(&self, server_name: ServerName<'static>, value: Tls13ClientSessionValue) {
+        self.0.read().unwrap().insert_tls13_ticket(server_name, value)
+    }
+
+    fn take_tls13_ticket(&self, server_name: &ServerName<'static>) -> Option<Tls13ClientSessionValue> {
+        self.0.read().unwrap().take_tls13_ticket(server_name)
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::fs;
+
+    use futures_rustls::rustls::{CertificateError, Error};
+    use rcgen::{CertificateParams, SerialNumber};
+
+    use super::CertificateRevocation;
+    use crate::test_util::{https_get, pem, server_config, temp_path, Ca};
+    use crate::{Connector, HttpError, TlsConfigBuilder, TransportError};
+
+    fn leaf(ca: &Ca, serial: u64) -> (rustls_pki_types::CertificateDer<'static>, rustls_pki_types::PrivateKeyDer<'static>) {
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.serial_number = Some(SerialNumber::from(serial));
+        ca.issue(params)
+    }
+
+    fn connector(ca: &Ca, revocation: &CertificateRevocation) -> Connector {
+        let builder = TlsConfigBuilder::new().with_root_certificate(ca.der());
+        Connector::new(builder.with_certificate_revocation(revocation.clone()).build().unwrap())
+    }
+
+    fn is_revoked(result: Result<String, HttpError>) -> bool {
+        let Err(HttpError::ConnectError(TransportError::TlsConnect(err))) = result else {
+            return false;
+        };
+        let err = err.get_ref().and_then(|err| err.downcast_ref::<Error>());
+        matches!(err, Some(Error::InvalidCertificate(CertificateError::Revoked)))
+    }
+
+    #[test]
+    fn revoked_certificate_rejected() {
+        let ca = Ca::new("root");
+        let revocation = CertificateRevocation::new().with_crl(ca.crl(&[2]));
+        let connector = connector(&ca, &revocation);
+        let (certificate, key) = leaf(&ca, 1);
+        assert_eq!(https_get(server_config(vec![certificate], key), connector.clone()).unwrap(), "ok");
+        let (certificate, key) = leaf(&ca, 2);
+        assert!(is_revoked(https_get(server_config(vec![certificate], key), connector)));
+    }
+
+    #[test]
+    fn unknown_status() {
+        let ca = Ca::new("root");
+        let revocation = CertificateRevocation::new().with_crl(Ca::new("other").crl(&[]));
+        let (certificate, key) = leaf(&ca, 1);
+        let config = server_config(vec![certificate], key);
+        assert!(https_get(config.clone(), connector(&ca, &revocation)).is_err());
+        let revocation = revocation.with_unknown_status_allowed(true);
+        assert_eq!(https_get(config, connector(&ca, &revocation)).unwrap(), "ok");
+    }
+
+    #[test]
+    fn refresh() {
+        let ca = Ca::new("root");
+        let path = temp_path("crls.pem");
+        fs::write(&path, pem("X509 CRL", &ca.crl(&[]))).unwrap();
+        let revocation = CertificateRevocation::new().with_crl_file(&path);
+        let connector = connector(&ca, &revocation);
+        let (certificate, key) = leaf(&ca, 7);
+        let config = server_config(vec![certificate], key);
+        assert_eq!(https_get(config.clone(), connector.clone()).unwrap(), "ok");
+        fs::write(&path, pem("X509 CRL", &ca.crl(&[7]))).unwrap();
+        revocation.refresh().unwrap();
+        assert!(is_revoked(https_get(config.clone(), connector.clone())));
+        // failed refreshes keep the loaded CRLs
+        fs::write(&path, "not a crl").unwrap();
+        assert!(revocation.refresh().is_err());
+        fs::remove_file(&path).unwrap();
+        assert!(is_revoked(https_get(config, connector)));
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    io,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
@@ -8,27 +9,36 @@ use async_ws::{
     connection::WsConfig,
     http::{check_upgrade_response, is_upgrade_request, upgrade_request},
 };
-use futures::{AsyncReadExt, Stream};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, Stream};
 use http::Response;
 
-use crate::{http::RequestWithoutBodyExt, Transport};
+use crate::{abort::AbortListener, http::RequestWithoutBodyExt, lifecycle::InFlight, AbortHandle, ConnectionInfo, Connector, HttpError, Transport};
 
 mod error;
 
 use error::*;
 
 pub type WsMessageKind = async_ws::message::WsMessageKind;
-pub type WsSend = async_ws::connection::WsSend<Transport>;
+pub type WsSend = async_ws::connection::WsSend<AbortableTransport>;
 pub type WsConnectionError = async_ws::connection::WsConnectionError;
-pub type WsMessageReader = async_ws::connection::WsMessageReader<Transport>;
-pub type WsMessageWriter = async_ws::connection::WsMessageWriter<Transport>;
+pub type WsMessageReader = async_ws::connection::WsMessageReader<AbortableTransport>;
+pub type WsMessageWriter = async_ws::connection::WsMessageWriter<AbortableTransport>;
 
 pub struct WsConnection {
-    inner: async_ws::connection::WsConnection<Transport>,
+    inner: async_ws::connection::WsConnection<AbortableTransport>,
+    info: Option<ConnectionInfo>,
 }
 
 impl WsConnection {
+    #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
     pub async fn connect_with_uri<T>(uri: T) -> Result<Self, WsConnectError>
+    where
+        http::Uri: TryFrom<T>,
+        <http::Uri as TryFrom<T>>::Error: Into<http::uri::InvalidUri>,
+    {
+        Self::connect_with_uri_and_connector(uri, Connector::default()).await
+    }
+    pub async fn connect_with_uri_and_connector<T>(uri: T, connector: Connector) -> Result<Self, WsConnectError>
     where
         http::Uri: TryFrom<T>,
         <http::Uri as TryFrom<T>>::Error: Into<http::uri::InvalidUri>,
@@ -36,13 +46,18 @@ impl WsConnection {
         let uri: http::Uri = uri.try_into().map_err(Into::into)?;
         let mut request = Self::connect_request_builder().body(()).unwrap();
         *request.uri_mut() = uri;
-        Self::connect(&request).await
+        Self::connect_with_connector(&request, connector).await
     }
+    #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
     pub async fn connect(request: &http::Request<()>) -> Result<Self, WsConnectError> {
+        Self::connect_with_connector(request, Connector::default()).await
+    }
+    pub async fn connect_with_connector(request: &http::Request<()>, connector: Connector) -> Result<Self, WsConnectError> {
         if !is_upgrade_request(request) {
             return Err(WsConnectError::InvalidUpgradeRequest);
         }
-        let response = request.send(()).await?;
+        let lifecycle = connector.lifecycle().clone();
+        let response = request.send_with_connector((), connector).await?;
         if !check_upgrade_response(request, &response) {
             let (head, body_reader) = response.into_parts();
             let mut buf = Vec::new();
@@ -54,9 +69,19 @@ impl WsConnection {
             let response = Response::from_parts(head, result);
             return Err(WsConnectError::InvalidUpgradeResponse(response.into()));
         }
+        let info = response.extensions().get::<ConnectionInfo>().cloned();
+        // tracked before the response body stops tracking the request
+        let (in_flight, shutdown) = lifecycle.track();
         let transport = response.into_body().into_inner()?.1;
+        let abort = request
+            .extensions()
+            .get::<AbortHandle>()
+            .map(AbortHandle::listen)
+            .into_iter()
+            .chain([shutdown]);
+        let transport = AbortableTransport::new(transport, abort.collect(), in_flight);
         let inner = async_ws::connection::WsConnection::with_config(transport, WsConfig::client());
-        Ok(Self { inner })
+        Ok(Self { inner, info })
     }
     pub fn connect_request_builder() -> http::request::Builder {
         upgrade_request()
@@ -73,6 +98,10 @@ impl WsConnection {
     pub fn err(&self) -> Option<Arc<WsConnectionError>> {
         self.inner.err()
     }
+    /// Details about the underlying connection.
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.info.as_ref()
+    }
 }
 
 impl Stream for WsConnection {
@@ -82,3 +111,50 @@ impl Stream for WsConnection {
         Pin::new(&mut self.get_mut().inner).poll_next(cx)
     }
 }
+
+/// Transport of websocket connections, closed once its [`AbortHandle`] is aborted or its connector shut down,
+/// failing all further operations. Wraps the [`Transport`] of [`WsSend`], [`WsMessageReader`] and [`WsMessageWriter`].
+pub struct AbortableTransport {
+    transport: Option<Transport>,
+    abort: Vec<AbortListener>,
+    _in_flight: InFlight,
+}
+
+impl AbortableTransport {
+    pub(crate) fn new(transport: Transport, abort: Vec<AbortListener>, in_flight: InFlight) -> Self {
+        Self {
+            transport: Some(transport),
+            abort,
+            _in_flight: in_flight,
+        }
+    }
+    fn poll_transport(&mut self, cx: &mut Context<'_>) -> io::Result<Pin<&mut Transport>> {
+        if self.abort.iter().any(|abort| abort.poll_aborted(cx).is_ready()) {
+            if let Some(transport) = self.transport.take() {
+                transport.close_now();
+            }
+        }
+        match &mut self.transport {
+            Some(transport) => Ok(Pin::new(transport)),
+            None => Err(HttpError::Aborted.into()),
+        }
+    }
+}
+
+impl AsyncRead for AbortableTransport {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_transport(cx)?.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for AbortableTransport {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_transport(cx)?.poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_transport(cx)?.poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_transport(cx)?.poll_close(cx)
+    }
+}